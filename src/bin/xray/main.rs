@@ -0,0 +1,251 @@
+//! Command line companion to the xray library, for managing the output of screenshot tests.
+//!
+//! Run `xray help` for usage.
+
+extern crate image;
+extern crate xray;
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::process;
+
+mod review;
+
+use xray::{
+    compare_images, diff_images, find_failed_tests, find_pending_references, find_references, prune_test_records, read_test_record, remove_test_output,
+    write_html_report, write_junit_report, Config, FsScreenshotIo, Tolerance, JUNIT_FILE_NAME, MASK_SUFFIX,
+    PENDING_REFERENCE_SUFFIX, REPORT_FILE_NAME, VARIANT_SEPARATOR
+};
+
+const USAGE: &str = "Usage: xray [--references <dir>] [--test-output <dir>] <command> [args]
+
+Commands:
+    list                             List failed tests found in the test output directory
+    review [--no-preview]            Step through failed tests, accepting, rejecting or skipping
+                                     each with a single key
+    accept (--all | <test name>...)  Replace references with their pending .new.png references
+    reject (--all | <test name>...)  Delete pending .new.png references
+    diff <actual.png> <expected.png> [--output <diff.png>]
+                                     Compare two images, optionally writing a diff image
+    report                           Rebuild the HTML and JUnit XML reports from the recorded
+                                     test results
+    clean [--all]                    Remove test output for tests with no pending reference,
+                                     or all test output with --all
+    flip-references (--all | <test name>...)
+                                     Flip references (and their masks and pending references)
+                                     upside down, to migrate references captured by OpenGL
+                                     before captures were stored top row first
+    help                             Show this message
+
+Options:
+    --references <dir>   Directory containing reference images (default: references, or as set in xray.toml)
+    --test-output <dir>  Directory containing test output (default: test_output, or as set in xray.toml)";
+
+/// Exit code used when the command ran successfully but found differences or failures.
+const EXIT_DIFFERENCES: i32 = 1;
+/// Exit code used when the command could not be run.
+const EXIT_ERROR: i32 = 2;
+
+struct Paths {
+    references: PathBuf,
+    test_output: PathBuf,
+    variants: Option<Vec<String>>
+}
+
+impl Paths {
+    /// The `FsScreenshotIo` for `test_name`, using the reference variant recorded by the test's last run,
+    /// or the variants configured in `xray.toml` if it has not recorded one. Names which already include
+    /// a variant key, such as those of pending references, are used as they are.
+    fn screenshot_io(&self, test_name: &str) -> FsScreenshotIo {
+        let screenshot_io = FsScreenshotIo::new(test_name, &self.references, &self.test_output);
+        if test_name.contains(VARIANT_SEPARATOR) {
+            return screenshot_io.with_variant_keys(Vec::new());
+        }
+        match (read_test_record(&self.test_output, test_name), &self.variants) {
+            (Some(record), _) => screenshot_io.with_variant_keys(record.reference_variant.into_iter().collect()),
+            (None, Some(variants)) => screenshot_io.with_variant_keys(variants.clone()),
+            (None, None) => screenshot_io
+        }
+    }
+}
+
+type CliResult = Result<i32, String>;
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    match run(args) {
+        Ok(code) => process::exit(code),
+        Err(message) => {
+            eprintln!("xray: {}\n\n{}", message, USAGE);
+            process::exit(EXIT_ERROR);
+        }
+    }
+}
+
+/// Removes the value following `flag` from `args`, returning it if the flag was present.
+fn take_option(args: &mut Vec<String>, flag: &str) -> Result<Option<String>, String> {
+    match args.iter().position(|arg| arg == flag) {
+        Some(index) if index + 1 < args.len() => {
+            let value = args.remove(index + 1);
+            args.remove(index);
+            Ok(Some(value))
+        },
+        Some(_) => Err(format!("{} requires a value", flag)),
+        None => Ok(None)
+    }
+}
+
+/// Removes `flag` from `args`, returning whether it was present.
+fn take_flag(args: &mut Vec<String>, flag: &str) -> bool {
+    let before = args.len();
+    args.retain(|arg| arg != flag);
+    args.len() != before
+}
+
+fn run(mut args: Vec<String>) -> CliResult {
+    let config = Config::load().map_err(|err| err.to_string())?;
+    let paths = Paths {
+        references: take_option(&mut args, "--references")?.map(PathBuf::from).unwrap_or(config.references),
+        test_output: take_option(&mut args, "--test-output")?.map(PathBuf::from).unwrap_or(config.test_output),
+        variants: config.variants
+    };
+    if args.is_empty() {
+        return Err("no command given".to_string());
+    }
+    let command = args.remove(0);
+    match command.as_str() {
+        "list" => list(&paths),
+        "review" => review::review(&paths, args),
+        "accept" => review_pending(&paths, args, true),
+        "reject" => review_pending(&paths, args, false),
+        "diff" => diff(args),
+        "report" => report(&paths),
+        "clean" => clean(&paths, args),
+        "flip-references" => flip_references(&paths, args),
+        "help" | "--help" | "-h" => {
+            println!("{}", USAGE);
+            Ok(0)
+        },
+        _ => Err(format!("unknown command '{}'", command))
+    }
+}
+
+fn list(paths: &Paths) -> CliResult {
+    let failed = find_failed_tests(&paths.test_output).map_err(|err| err.to_string())?;
+    for test_name in &failed {
+        let screenshot_io = paths.screenshot_io(test_name);
+        let has_expected = paths.test_output.join(test_name).join("expected.png").exists();
+        let status = if !has_expected { "no reference" } else { "mismatch" };
+        let pending = if screenshot_io.has_pending_reference() { ", pending reference" } else { "" };
+        println!("{} ({}{})", test_name, status, pending);
+    }
+    Ok(if failed.is_empty() { 0 } else { EXIT_DIFFERENCES })
+}
+
+fn review_pending(paths: &Paths, mut args: Vec<String>, accept: bool) -> CliResult {
+    let test_names = if take_flag(&mut args, "--all") {
+        find_pending_references(&paths.references).map_err(|err| err.to_string())?
+    } else if args.is_empty() {
+        return Err("expected --all or at least one test name".to_string());
+    } else {
+        args
+    };
+
+    let mut missing = false;
+    for test_name in &test_names {
+        let screenshot_io = paths.screenshot_io(test_name);
+        let result = if accept {
+            screenshot_io.accept_pending_reference()
+        } else {
+            screenshot_io.reject_pending_reference()
+        };
+        match result {
+            Ok(true) => println!("{} {}", if accept { "Accepted" } else { "Rejected" }, test_name),
+            Ok(false) => {
+                eprintln!("No pending reference for {}", test_name);
+                missing = true;
+            },
+            Err(err) => return Err(format!("{}: {}", test_name, err))
+        }
+    }
+    Ok(if missing { EXIT_DIFFERENCES } else { 0 })
+}
+
+fn diff(mut args: Vec<String>) -> CliResult {
+    let output = take_option(&mut args, "--output")?;
+    if args.len() != 2 {
+        return Err("diff expects an actual and an expected image".to_string());
+    }
+    let actual = image::open(&args[0]).map_err(|err| format!("could not load {}: {}", args[0], err))?;
+    let expected = image::open(&args[1]).map_err(|err| format!("could not load {}: {}", args[1], err))?;
+
+    let stats = compare_images(&actual, &expected, &Tolerance::exact());
+    println!("{}", stats);
+    if let Some(output) = output {
+        diff_images(&actual, &expected).save(&output).map_err(|err| format!("could not write {}: {}", output, err))?;
+    }
+    Ok(if stats.is_match() { 0 } else { EXIT_DIFFERENCES })
+}
+
+fn report(paths: &Paths) -> CliResult {
+    for test_name in prune_test_records(&paths.test_output, &paths.references).map_err(|err| err.to_string())? {
+        println!("Removed output for {}, which has no reference image", test_name);
+    }
+    write_html_report(&paths.test_output).map_err(|err| err.to_string())?;
+    write_junit_report(&paths.test_output).map_err(|err| err.to_string())?;
+    println!("Wrote {}", paths.test_output.join(REPORT_FILE_NAME).display());
+    println!("Wrote {}", paths.test_output.join(JUNIT_FILE_NAME).display());
+    Ok(0)
+}
+
+fn clean(paths: &Paths, mut args: Vec<String>) -> CliResult {
+    let all = take_flag(&mut args, "--all");
+    if !args.is_empty() {
+        return Err(format!("unexpected arguments: {}", args.join(" ")));
+    }
+    if all {
+        if paths.test_output.exists() {
+            fs::remove_dir_all(&paths.test_output).map_err(|err| err.to_string())?;
+            println!("Removed {}", paths.test_output.display());
+        }
+        return Ok(0);
+    }
+
+    for test_name in find_failed_tests(&paths.test_output).map_err(|err| err.to_string())? {
+        if !paths.screenshot_io(&test_name).has_pending_reference() {
+            remove_test_output(&paths.test_output, &test_name).map_err(|err| err.to_string())?;
+            println!("Removed output for {}", test_name);
+        }
+    }
+    Ok(0)
+}
+
+fn flip_references(paths: &Paths, mut args: Vec<String>) -> CliResult {
+    let test_names = if take_flag(&mut args, "--all") {
+        find_references(&paths.references).map_err(|err| err.to_string())?
+    } else if args.is_empty() {
+        return Err("expected --all or at least one test name".to_string());
+    } else {
+        args
+    };
+
+    let mut missing = false;
+    for test_name in &test_names {
+        let reference = paths.references.join(format!("{}.png", test_name));
+        if !reference.is_file() {
+            eprintln!("No reference for {}", test_name);
+            missing = true;
+            continue;
+        }
+        for suffix in &[".png", MASK_SUFFIX, PENDING_REFERENCE_SUFFIX] {
+            let path = paths.references.join(format!("{}{}", test_name, suffix));
+            if path.is_file() {
+                let image = image::open(&path).map_err(|err| format!("could not load {}: {}", path.display(), err))?;
+                image.flipv().save(&path).map_err(|err| format!("could not write {}: {}", path.display(), err))?;
+                println!("Flipped {}", path.display());
+            }
+        }
+    }
+    Ok(if missing { EXIT_DIFFERENCES } else { 0 })
+}
@@ -0,0 +1,248 @@
+//! `xray review`: walks through each failed test, showing its metrics and a preview of its images
+//! in the terminal, and accepts, rejects or skips the change with a single key.
+
+use std::env;
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+use image::{self, DynamicImage, FilterType, Rgba};
+use xray::{compare_images, find_failed_tests, read_test_record, remove_test_output, ScreenshotIo, TestStatus, Tolerance};
+
+use super::{take_flag, CliResult, Paths, EXIT_DIFFERENCES};
+
+/// The tallest preview to draw, in pixels. Each line of the terminal shows two rows of pixels.
+const MAX_PREVIEW_HEIGHT: u32 = 32;
+const PANEL_GAP: usize = 2;
+
+/// Puts the terminal into non-canonical mode so single key presses can be read without
+/// waiting for enter, restoring the previous settings when dropped.
+///
+/// Signal keys are turned off too, so Ctrl-C is read as a key that quits the review instead of
+/// killing the process before the settings can be restored.
+struct RawTerminal {
+    saved_settings: String
+}
+
+fn stty(args: &[&str]) -> Option<String> {
+    let output = Command::new("stty")
+        .args(args)
+        .stdin(Stdio::inherit())
+        .stderr(Stdio::null())
+        .output()
+        .ok()?;
+    if output.status.success() {
+        Some(String::from_utf8_lossy(&output.stdout).trim().to_string())
+    } else {
+        None
+    }
+}
+
+impl RawTerminal {
+    /// Returns `None` if stdin is not a terminal, in which case keys are read a line at a time.
+    fn enable() -> Option<RawTerminal> {
+        let saved_settings = stty(&["-g"])?;
+        stty(&["-icanon", "-echo", "-isig", "min", "1"])?;
+        Some(RawTerminal { saved_settings })
+    }
+}
+
+impl Drop for RawTerminal {
+    fn drop(&mut self) {
+        stty(&[&self.saved_settings]);
+    }
+}
+
+/// Reads a single key press, or the first character of a line if the terminal is not in raw mode.
+fn read_key(raw: bool) -> io::Result<Option<char>> {
+    let stdin = io::stdin();
+    if raw {
+        let mut buffer = [0; 1];
+        let read = stdin.lock().read(&mut buffer)?;
+        Ok(if read == 0 { None } else { Some(buffer[0] as char) })
+    } else {
+        let mut line = String::new();
+        let read = stdin.read_line(&mut line)?;
+        Ok(if read == 0 { None } else { line.trim().chars().next().or(Some('\n')) })
+    }
+}
+
+/// The key read when Ctrl-C is pressed with signal keys turned off.
+const CTRL_C: char = '\u{3}';
+/// The key read when Ctrl-D is pressed in non-canonical mode.
+const CTRL_D: char = '\u{4}';
+
+/// The width of the terminal from `$COLUMNS`, which shells rarely export, or else `stty size`.
+fn terminal_width() -> usize {
+    env::var("COLUMNS").ok()
+        .and_then(|columns| columns.parse().ok())
+        .or_else(|| stty(&["size"])?.split_whitespace().nth(1)?.parse().ok())
+        .filter(|&width| width > 0)
+        .unwrap_or(80)
+}
+
+/// Composites a possibly transparent pixel over a grey checkerboard so transparency is visible.
+fn composite(pixel: Rgba<u8>, x: u32, y: u32) -> [u8; 3] {
+    #[allow(clippy::manual_is_multiple_of)] // is_multiple_of needs Rust 1.87
+    let background = if (x / 2 + y / 2) % 2 == 0 { 48.0 } else { 80.0 };
+    let alpha = f64::from(pixel.data[3]) / 255.0;
+    let blend = |channel: u8| (f64::from(channel) * alpha + background * (1.0 - alpha)).round() as u8;
+    [blend(pixel.data[0]), blend(pixel.data[1]), blend(pixel.data[2])]
+}
+
+/// Renders `image` scaled to fit within `max_width` columns, using truecolor escape codes and
+/// upper half block characters so that each character cell shows two pixels.
+/// Returns the width of the preview in columns, and one string per line of output.
+fn render_preview(image: &DynamicImage, max_width: u32) -> (usize, Vec<String>) {
+    let scaled = image.resize(max_width.max(1), MAX_PREVIEW_HEIGHT, FilterType::Triangle).to_rgba();
+    let (width, height) = scaled.dimensions();
+    let mut lines = Vec::new();
+    for y in (0..height).step_by(2) {
+        let mut line = String::new();
+        for x in 0..width {
+            let [tr, tg, tb] = composite(*scaled.get_pixel(x, y), x, y);
+            let [br, bg, bb] = if y + 1 < height {
+                composite(*scaled.get_pixel(x, y + 1), x, y + 1)
+            } else {
+                [0, 0, 0]
+            };
+            line.push_str(&format!("\x1b[38;2;{};{};{}m\x1b[48;2;{};{};{}m\u{2580}", tr, tg, tb, br, bg, bb));
+        }
+        line.push_str("\x1b[0m");
+        lines.push(line);
+    }
+    (width as usize, lines)
+}
+
+/// Draws the given images side by side, each under its label.
+fn print_previews(panels: &[(&str, Option<DynamicImage>)]) {
+    let panel_width = (terminal_width().saturating_sub(PANEL_GAP * panels.len()) / panels.len()).max(8) as u32;
+    let rendered: Vec<(usize, Vec<String>)> = panels.iter().map(|(label, image)| match image {
+        Some(image) => {
+            let (width, lines) = render_preview(image, panel_width);
+            (width.max(label.len()), lines)
+        },
+        None => (label.len().max("(missing)".len()), vec!["(missing)".to_string()])
+    }).collect();
+
+    let gap = " ".repeat(PANEL_GAP);
+    let labels: Vec<String> = panels.iter().zip(rendered.iter())
+        .map(|((label, _), (width, _))| format!("{:width$}", label, width = width))
+        .collect();
+    println!("{}", labels.join(&gap));
+    let rows = rendered.iter().map(|(_, lines)| lines.len()).max().unwrap_or(0);
+    for row in 0..rows {
+        let line: Vec<String> = rendered.iter().map(|(width, lines)| match lines.get(row) {
+            Some(line) if line.starts_with('\x1b') => line.clone(),
+            Some(line) => format!("{:width$}", line, width = width),
+            None => " ".repeat(*width)
+        }).collect();
+        println!("{}", line.join(&gap));
+    }
+}
+
+fn open_if_exists(path: &Path) -> Option<DynamicImage> {
+    if path.exists() {
+        image::open(path).ok()
+    } else {
+        None
+    }
+}
+
+enum Decision {
+    Accept,
+    Reject,
+    Skip,
+    Quit
+}
+
+fn ask(raw: bool) -> io::Result<Decision> {
+    loop {
+        print!("[a]ccept, [r]eject, [s]kip, [q]uit? ");
+        io::stdout().flush()?;
+        let key = read_key(raw)?;
+        println!();
+        match key {
+            Some('a') | Some('A') => return Ok(Decision::Accept),
+            Some('r') | Some('R') => return Ok(Decision::Reject),
+            Some('s') | Some('S') | Some(' ') | Some('\n') => return Ok(Decision::Skip),
+            Some('q') | Some('Q') | Some(CTRL_C) | Some(CTRL_D) | None => return Ok(Decision::Quit),
+            _ => {}
+        }
+    }
+}
+
+/// Accepts the change for a test, preferring its pending reference and falling back to
+/// the actual screenshot in its test output.
+/// 
+/// The actual screenshot is only used if the test's last recorded run failed or had no reference,
+/// so that a screenshot left over from an earlier failure never replaces a reference.
+fn accept(paths: &Paths, test_name: &str, actual: &Option<DynamicImage>) -> Result<(), String> {
+    let screenshot_io = paths.screenshot_io(test_name);
+    if screenshot_io.accept_pending_reference().map_err(|err| err.to_string())? {
+        return Ok(());
+    }
+    let status = read_test_record(&paths.test_output, test_name).map(|record| record.status);
+    match (actual, status) {
+        (Some(actual), Some(TestStatus::Failed)) | (Some(actual), Some(TestStatus::NoReference)) => {
+            screenshot_io.write_reference(actual).map_err(|err| err.to_string())
+        },
+        _ => Err(format!("{} has no pending reference or failed screenshot to accept", test_name))
+    }
+}
+
+pub(crate) fn review(paths: &Paths, mut args: Vec<String>) -> CliResult {
+    let show_preview = !take_flag(&mut args, "--no-preview");
+    if !args.is_empty() {
+        return Err(format!("unexpected arguments: {}", args.join(" ")));
+    }
+    let failed = find_failed_tests(&paths.test_output).map_err(|err| err.to_string())?;
+    if failed.is_empty() {
+        println!("No failed tests found in {}", paths.test_output.display());
+        return Ok(0);
+    }
+
+    let terminal = RawTerminal::enable();
+    let mut remaining = 0;
+    for (index, test_name) in failed.iter().enumerate() {
+        let output_dir = paths.test_output.join(test_name);
+        let expected_path = output_dir.join("expected.png");
+        let actual_path = output_dir.join("actual.png");
+        let diff_path = output_dir.join("diff.png");
+        let expected = open_if_exists(&expected_path);
+        let actual = open_if_exists(&actual_path);
+        let diff = open_if_exists(&diff_path);
+
+        println!("\n[{}/{}] {}", index + 1, failed.len(), test_name);
+        match (&actual, &expected) {
+            (Some(actual), Some(expected)) => println!("  {}", compare_images(actual, expected, &Tolerance::exact())),
+            (Some(_), None) => println!("  No reference image."),
+            _ => println!("  Could not load the actual screenshot.")
+        }
+        println!("  expected: {}", expected_path.display());
+        println!("  actual:   {}", actual_path.display());
+        println!("  diff:     {}", diff_path.display());
+        if show_preview {
+            print_previews(&[("expected", expected), ("actual", actual.clone()), ("diff", diff)]);
+        }
+
+        match ask(terminal.is_some()).map_err(|err| err.to_string())? {
+            Decision::Accept => {
+                accept(paths, test_name, &actual)?;
+                remove_test_output(&paths.test_output, test_name).map_err(|err| err.to_string())?;
+                println!("Accepted {}", test_name);
+            },
+            Decision::Reject => {
+                paths.screenshot_io(test_name).reject_pending_reference().map_err(|err| err.to_string())?;
+                remove_test_output(&paths.test_output, test_name).map_err(|err| err.to_string())?;
+                println!("Rejected {}", test_name);
+            },
+            Decision::Skip => remaining += 1,
+            Decision::Quit => {
+                remaining += failed.len() - index;
+                break;
+            }
+        }
+    }
+    Ok(if remaining == 0 { 0 } else { EXIT_DIFFERENCES })
+}
@@ -0,0 +1,373 @@
+//! `ScreenshotTest`, which configures and runs a single screenshot test.
+
+use std::time::Instant;
+
+use comparator::{ComparisonReport, ImageComparator, Tolerance, ToleranceComparator, Verdict};
+use config::{ComparatorConfig, Config};
+use mask::{find_changed_regions, MaskedComparator, Region};
+use report::{ImageSize, TestRecord, TestStatus};
+use {diff_images, CaptureError, DynamicImage, IoError, ScreenshotCaptor, ScreenshotError, ScreenshotIo, UpdateMode, XrayError, XrayResult};
+#[cfg(feature = "gl")]
+use {FsScreenshotIo, OpenGlScreenshotCaptor};
+
+/// Which output a `ScreenshotTest` writes through its `ScreenshotIo`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OutputPolicy {
+    /// Writes the actual, expected, diff and debug images and a pending reference for failed tests,
+    /// and the result of every test. This is the default.
+    #[default]
+    Full,
+    /// Like `Full`, but never writes a pending reference.
+    WithoutPendingReference,
+    /// Writes no images or results and leaves any pending reference in place, e.g. for tests which
+    /// check a screenshot many times while waiting for it to match. Reference images are still written by `UpdateMode::New` and `UpdateMode::All`.
+    Disabled
+}
+
+/// A screenshot test, configured with builder methods and run with `run` or `assert`.
+///
+/// ```no_run
+/// # extern crate xray;
+/// # use xray::{FsScreenshotIo, OpenGlScreenshotCaptor, Region, ScreenshotIo, ScreenshotTest, Tolerance};
+/// # fn main() {
+/// ScreenshotTest::new(FsScreenshotIo::default("menus/main"), OpenGlScreenshotCaptor::new())
+///     .with_region(0, 0, 1280, 720)
+///     .with_tolerance(Tolerance::exact().with_max_channel_delta(2))
+///     .with_ignore_region(Region::new(1200, 0, 80, 20))
+///     .assert();
+/// # }
+/// ```
+///
+/// Unless configured otherwise, the screenshot is compared using the comparator and ignore regions
+/// configured for the test in `xray.toml` (see `Config`), which by default requires every pixel to match
+/// exactly. Mask images from the `ScreenshotIo` are applied, all output is written and the `ScreenshotIo`'s
+/// update mode is used.
+pub struct ScreenshotTest<S, C, I = ComparatorConfig> {
+    screenshot_io: S,
+    screenshot_captor: C,
+    comparator: I,
+    region: Option<(i32, i32, u32, u32)>,
+    ignore_regions: Vec<Region>,
+    mask_images: bool,
+    output_policy: OutputPolicy,
+    update_mode: Option<UpdateMode>,
+    config_error: Option<XrayError>
+}
+
+impl<S: ScreenshotIo, C: ScreenshotCaptor> ScreenshotTest<S, C> {
+    /// Creates a test which reads and writes images with `screenshot_io` and captures the screenshot
+    /// with `screenshot_captor`. The region to capture must be set with `with_region`.
+    ///
+    /// The comparator and ignore regions are read from `Config::global()`, for the test named by
+    /// `ScreenshotIo::test_name`. If the configuration could not be loaded, `run` returns the error.
+    pub fn new(screenshot_io: S, screenshot_captor: C) -> ScreenshotTest<S, C> {
+        let default_config = Config::default();
+        let (config, config_error) = match Config::global() {
+            Ok(config) => (config, None),
+            Err(err) => (&default_config, Some(err))
+        };
+        let (comparator, ignore_regions) = match screenshot_io.test_name() {
+            Some(test_name) => (config.comparator(test_name), config.ignore_regions(test_name).to_vec()),
+            None => (config.comparator, Vec::new())
+        };
+        ScreenshotTest {
+            screenshot_io,
+            screenshot_captor,
+            comparator,
+            region: None,
+            ignore_regions,
+            mask_images: true,
+            output_policy: OutputPolicy::default(),
+            update_mode: None,
+            config_error
+        }
+    }
+}
+
+#[cfg(feature = "gl")]
+impl ScreenshotTest<FsScreenshotIo, OpenGlScreenshotCaptor> {
+    /// A test which captures the screenshot using OpenGL, and compares it to `references/<test_name>.png`.
+    /// This is the test run by `gl_screenshot_test`.
+    pub fn gl(test_name: &str) -> ScreenshotTest<FsScreenshotIo, OpenGlScreenshotCaptor> {
+        ScreenshotTest::new(FsScreenshotIo::default(test_name), OpenGlScreenshotCaptor::new())
+    }
+}
+
+impl<S: ScreenshotIo, C: ScreenshotCaptor, I: ImageComparator> ScreenshotTest<S, C, I> {
+    /// Captures the area (x, y, x + width, y + height).
+    pub fn with_region(self, x: i32, y: i32, width: u32, height: u32) -> ScreenshotTest<S, C, I> {
+        ScreenshotTest { region: Some((x, y, width, height)), ..self }
+    }
+
+    /// Uses `comparator` to decide whether the screenshot matches the reference image.
+    pub fn with_comparator<J: ImageComparator>(self, comparator: J) -> ScreenshotTest<S, C, J> {
+        ScreenshotTest {
+            screenshot_io: self.screenshot_io,
+            screenshot_captor: self.screenshot_captor,
+            comparator,
+            region: self.region,
+            ignore_regions: self.ignore_regions,
+            mask_images: self.mask_images,
+            output_policy: self.output_policy,
+            update_mode: self.update_mode,
+            config_error: self.config_error
+        }
+    }
+
+    /// Allows differences within `tolerance`, using a `ToleranceComparator`.
+    pub fn with_tolerance(self, tolerance: Tolerance) -> ScreenshotTest<S, C, ToleranceComparator> {
+        self.with_comparator(ToleranceComparator::new(tolerance))
+    }
+
+    /// Ignores differences inside `region`, as `MaskedComparator::with_region` does.
+    pub fn with_ignore_region(mut self, region: Region) -> ScreenshotTest<S, C, I> {
+        self.ignore_regions.push(region);
+        self
+    }
+
+    /// Whether to ignore the areas marked by the mask image from `ScreenshotIo::load_mask`. Defaults to true.
+    pub fn with_mask_images(self, mask_images: bool) -> ScreenshotTest<S, C, I> {
+        ScreenshotTest { mask_images, ..self }
+    }
+
+    /// Sets which output the test writes, `OutputPolicy::Full` by default.
+    pub fn with_output_policy(self, output_policy: OutputPolicy) -> ScreenshotTest<S, C, I> {
+        ScreenshotTest { output_policy, ..self }
+    }
+
+    /// Overrides the update mode of the `ScreenshotIo`.
+    pub fn with_update_mode(self, update_mode: UpdateMode) -> ScreenshotTest<S, C, I> {
+        ScreenshotTest { update_mode: Some(update_mode), ..self }
+    }
+
+    /// Runs the test, returning `Ok(())` if the screenshot matches the reference image, or was written
+    /// as the new reference image by the update mode.
+    pub fn run(mut self) -> XrayResult<()> {
+        if let Some(err) = self.config_error.take() {
+            return Err(err);
+        }
+        let mut record = TestRecord::new(TestStatus::Passed, "");
+        let started = Instant::now();
+        let result = self.capture_and_compare(&mut record);
+        record.timings.total = started.elapsed();
+        match result {
+            Ok(report) => {
+                if self.output_policy != OutputPolicy::Disabled {
+                    self.screenshot_io.clear_pending_reference()?;
+                }
+                record.summary = report.summary.clone();
+                record.set_report(&report);
+                self.write_result(&record)
+            },
+            Err(err) => self.handle_error(err, record)
+        }
+    }
+
+    /// Runs the test, and panics with the error if it fails.
+    pub fn assert(self) {
+        if let Err(err) = self.run() {
+            panic!("{}", err)
+        }
+    }
+
+    fn update_mode(&self) -> UpdateMode {
+        self.update_mode.unwrap_or_else(|| self.screenshot_io.update_mode())
+    }
+
+    fn write_result(&self, record: &TestRecord) -> XrayResult<()> {
+        if self.output_policy == OutputPolicy::Disabled {
+            return Ok(());
+        }
+        self.screenshot_io.write_result(record)
+    }
+
+    /// Captures the screenshot and compares it with the reference image, filling in the image sizes,
+    /// changed regions and timings of `record` along the way.
+    fn capture_and_compare(&self, record: &mut TestRecord) -> XrayResult<ComparisonReport> {
+        let (x, y, width, height) = self.region.ok_or_else(|| XrayError::CaptureError(
+            CaptureError::new("no region to capture was set with ScreenshotTest::with_region", 0, 0, 0, 0)
+        ))?;
+        let capture_started = Instant::now();
+        let captured_image = self.screenshot_captor.capture_image(x, y, width, height)?;
+        record.timings.capture = capture_started.elapsed();
+        record.actual_size = Some(ImageSize::of(&captured_image));
+
+        let reference_image = match self.screenshot_io.load_reference() {
+            Ok(reference_image) => reference_image,
+            Err(XrayError::Io(IoError::ReferenceNotFound(_))) => {
+                return Err(XrayError::Screenshot(ScreenshotError::NoReferenceScreenshot(captured_image)))
+            },
+            Err(err) => return Err(err)
+        };
+        record.expected_size = Some(ImageSize::of(&reference_image));
+        record.reference_variant = self.screenshot_io.reference_variant();
+
+        let mut comparator = MaskedComparator::new(&self.comparator);
+        comparator.regions = self.ignore_regions.clone();
+        if self.mask_images {
+            if let Some(mask) = self.screenshot_io.load_mask()? {
+                comparator = comparator.with_mask_image(&mask);
+            }
+        }
+        record.changed_regions = find_changed_regions(&captured_image, &reference_image, |x, y| comparator.is_masked(x, y));
+        let compare_started = Instant::now();
+        let result = if comparator.mask_image.is_some() || !comparator.regions.is_empty() {
+            compare_screenshot_images(reference_image, captured_image, &comparator)
+        } else {
+            compare_screenshot_images(reference_image, captured_image, comparator.comparator)
+        };
+        record.timings.compare = compare_started.elapsed();
+        result
+    }
+
+    fn update_reference(&self, screenshot_error: &XrayError) -> Option<XrayResult<()>> {
+        let screenshot_io = &self.screenshot_io;
+        match (self.update_mode(), screenshot_error) {
+            (UpdateMode::New, XrayError::Screenshot(ScreenshotError::NoReferenceScreenshot(actual))) |
+            (UpdateMode::All, XrayError::Screenshot(ScreenshotError::NoReferenceScreenshot(actual))) |
+            (UpdateMode::All, XrayError::Screenshot(ScreenshotError::ScreenshotMismatch(actual, _, _))) => {
+                let result = screenshot_io.write_reference(actual);
+                if self.output_policy == OutputPolicy::Disabled {
+                    return Some(result);
+                }
+                Some(result.and_then(|_| screenshot_io.clear_pending_reference()))
+            },
+            _ => None
+        }
+    }
+
+    fn handle_error(self, screenshot_error: XrayError, mut record: TestRecord) -> XrayResult<()> {
+        if let XrayError::Screenshot(ScreenshotError::ScreenshotMismatch(_, _, ref report)) = screenshot_error {
+            record.set_report(report);
+        }
+        if let Some(result) = self.update_reference(&screenshot_error) {
+            record.status = TestStatus::Updated;
+            record.summary = format!("Reference image updated from the captured screenshot.\n{}", screenshot_error);
+            return result.and_then(|_| self.write_result(&record));
+        }
+        if self.output_policy == OutputPolicy::Disabled {
+            return Err(screenshot_error);
+        }
+
+        let screenshot_io = &self.screenshot_io;
+        let write_pending_reference = self.output_policy == OutputPolicy::Full;
+        screenshot_io.prepare_output()?;
+        match screenshot_error {
+            XrayError::Screenshot(ScreenshotError::NoReferenceScreenshot(ref img)) => {
+                screenshot_io.write_actual(img)?;
+                if write_pending_reference {
+                    screenshot_io.write_pending_reference(img)?;
+                }
+            },
+            XrayError::Screenshot(ScreenshotError::ScreenshotMismatch(ref actual, ref expected, ref report)) => {
+                screenshot_io.write_expected(expected)?;
+                screenshot_io.write_actual(actual)?;
+                if write_pending_reference {
+                    screenshot_io.write_pending_reference(actual)?;
+                }
+                match report.diff_image {
+                    Some(ref diff) => screenshot_io.write_diff(diff)?,
+                    None => screenshot_io.write_diff(&diff_images(actual, expected))?
+                }
+                for (name, image) in &report.debug_images {
+                    screenshot_io.write_debug_image(name, image)?;
+                }
+            },
+            _ => {}
+        }
+        record.status = TestStatus::for_error(&screenshot_error);
+        record.summary = screenshot_error.to_string();
+        self.write_result(&record)?;
+        Err(screenshot_error)
+    }
+}
+
+fn compare_screenshot_images<I: ImageComparator>(reference_image: DynamicImage, actual_image: DynamicImage, comparator: &I) -> XrayResult<ComparisonReport> {
+    match comparator.compare(&actual_image, &reference_image) {
+        Verdict::Match(report) => Ok(report),
+        Verdict::Mismatch(report) => Err(XrayError::Screenshot(ScreenshotError::ScreenshotMismatch(actual_image, reference_image, Box::new(report))))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{ImageBuffer, Rgba};
+    use tests::{rbgw, rgbw, FakeScreenshotCaptor, FakeScreenshotIo};
+
+    #[test]
+    fn test_builder_defaults() {
+        let screenshot_io = FakeScreenshotIo::new(rgbw());
+        let result = ScreenshotTest::new(&screenshot_io, FakeScreenshotCaptor { screenshot: rbgw() })
+            .with_region(0, 0, 2, 2)
+            .run();
+        match result {
+            Err(XrayError::Screenshot(ScreenshotError::ScreenshotMismatch(_, _, report))) => assert_eq!(report.comparator, "exact"),
+            _ => panic!("Expected a screenshot mismatch")
+        }
+        assert!(screenshot_io.pending_reference.borrow().is_some());
+        assert!(screenshot_io.result.borrow().is_some());
+    }
+
+    #[test]
+    fn test_builder_missing_region() {
+        let result = ScreenshotTest::new(FakeScreenshotIo::new(rgbw()), FakeScreenshotCaptor { screenshot: rgbw() }).run();
+        match result {
+            Err(XrayError::CaptureError(err)) => assert!(err.reason.contains("with_region")),
+            _ => panic!("Expected a capture error")
+        }
+    }
+
+    #[test]
+    fn test_builder_ignore_regions_and_tolerance() {
+        // rbgw differs from rgbw in the top right and bottom left pixels.
+        let test = |screenshot_io| ScreenshotTest::new(screenshot_io, FakeScreenshotCaptor { screenshot: rbgw() }).with_region(0, 0, 2, 2);
+        assert!(test(FakeScreenshotIo::new(rgbw()))
+            .with_ignore_region(Region::new(1, 0, 1, 1))
+            .with_ignore_region(Region::new(0, 1, 1, 1))
+            .run()
+            .is_ok());
+        assert!(test(FakeScreenshotIo::new(rgbw()))
+            .with_tolerance(Tolerance::exact().with_max_differing_pixels(2))
+            .run()
+            .is_ok());
+        let mask = DynamicImage::ImageRgba8(ImageBuffer::from_pixel(2, 2, Rgba { data: [255, 255, 255, 255] }));
+        assert!(test(FakeScreenshotIo::new(rgbw()).with_mask(mask)).with_mask_images(false).run().is_err());
+    }
+
+    #[test]
+    fn test_builder_output_policy_and_update_mode() {
+        let screenshot_io = FakeScreenshotIo::new(rgbw());
+        let test = || ScreenshotTest::new(&screenshot_io, FakeScreenshotCaptor { screenshot: rbgw() }).with_region(0, 0, 2, 2);
+        assert!(test().with_output_policy(OutputPolicy::WithoutPendingReference).run().is_err());
+        assert!(screenshot_io.actual.borrow().is_some());
+        assert!(screenshot_io.pending_reference.borrow().is_none());
+
+        let screenshot_io = FakeScreenshotIo::new(rgbw());
+        let test = || ScreenshotTest::new(&screenshot_io, FakeScreenshotCaptor { screenshot: rbgw() }).with_region(0, 0, 2, 2);
+        assert!(test().with_output_policy(OutputPolicy::Disabled).run().is_err());
+        assert!(screenshot_io.actual.borrow().is_none());
+        assert!(screenshot_io.result.borrow().is_none());
+
+        // A match leaves the pending reference of an earlier failure alone too.
+        let matching_io = FakeScreenshotIo::new(rgbw());
+        matching_io.pending_reference.replace(Some(rbgw()));
+        assert!(ScreenshotTest::new(&matching_io, FakeScreenshotCaptor { screenshot: rgbw() })
+            .with_region(0, 0, 2, 2)
+            .with_output_policy(OutputPolicy::Disabled)
+            .run()
+            .is_ok());
+        assert!(matching_io.pending_reference.borrow().is_some());
+
+        // Updating the reference under Disabled keeps the pending reference too, while other policies clear it.
+        screenshot_io.pending_reference.replace(Some(rgbw()));
+        assert!(test().with_output_policy(OutputPolicy::Disabled).with_update_mode(UpdateMode::All).run().is_ok());
+        assert_eq!(screenshot_io.written_reference.borrow().as_ref().unwrap().raw_pixels(), rbgw().raw_pixels());
+        assert!(screenshot_io.pending_reference.borrow().is_some());
+        assert!(screenshot_io.result.borrow().is_none());
+
+        assert!(test().with_update_mode(UpdateMode::All).run().is_ok());
+        assert_eq!(screenshot_io.written_reference.borrow().as_ref().unwrap().raw_pixels(), rbgw().raw_pixels());
+        assert!(screenshot_io.pending_reference.borrow().is_none());
+    }
+}
@@ -0,0 +1,660 @@
+//! Strategies for deciding whether a captured screenshot matches its reference image.
+//!
+//! `xray` ships with these comparators:
+//!
+//! * `ExactComparator` requires every channel of every pixel to match.
+//! * `ToleranceComparator` allows small per-channel differences and a limited number of differing pixels.
+//! * `PerceptualComparator` measures the perceived difference between colours, rather than raw channel values,
+//!   and can optionally ignore anti-aliased pixels.
+//! * `SsimComparator` (in the `ssim` module) measures the structural similarity of the images.
+//!
+//! Custom comparison policies can be used by implementing `ImageComparator`.
+
+use std::fmt;
+
+use image::{DynamicImage, GenericImage, Rgba, RgbaImage};
+#[cfg(test)]
+use image::ImageBuffer;
+
+/// Compares a captured screenshot against a reference image.
+pub trait ImageComparator {
+    /// Compares the `actual` screenshot with the `expected` reference image and
+    /// decides whether they should be considered a match.
+    fn compare(&self, actual: &DynamicImage, expected: &DynamicImage) -> Verdict;
+}
+
+impl<I: ImageComparator + ?Sized> ImageComparator for &I {
+    fn compare(&self, actual: &DynamicImage, expected: &DynamicImage) -> Verdict {
+        (*self).compare(actual, expected)
+    }
+}
+
+/// Details of a comparison between a screenshot and its reference image.
+#[derive(Clone)]
+pub struct ComparisonReport {
+    /// The name of the comparator which produced this report.
+    pub comparator: String,
+    /// Statistics on the pixels which differed.
+    pub stats: DiffStats,
+    /// A human readable description of the differences, suitable for test failure messages.
+    pub summary: String,
+    /// An overall similarity score, for comparators which produce one (e.g. `SsimComparator`).
+    pub score: Option<f64>,
+    /// The named settings which the comparator used to decide the verdict, such as `max_channel_delta`.
+    pub thresholds: Vec<(String, f64)>,
+    /// An image highlighting the differences, for comparators which produce their own. If this is `None`,
+    /// the image produced by `diff_images` is written as the diff instead.
+    pub diff_image: Option<DynamicImage>,
+    /// Additional images to help debug a failed comparison, such as a similarity map.
+    /// These are written out with `ScreenshotIo::write_debug_image` using the paired name.
+    pub debug_images: Vec<(String, DynamicImage)>
+}
+
+impl ComparisonReport {
+    pub fn new(comparator: &str, stats: DiffStats, summary: String) -> ComparisonReport {
+        ComparisonReport {
+            comparator: comparator.to_string(),
+            stats,
+            summary,
+            score: None,
+            thresholds: Vec::new(),
+            diff_image: None,
+            debug_images: Vec::new()
+        }
+    }
+
+    /// Records a setting which the comparator used to decide the verdict.
+    pub fn with_threshold(mut self, name: &str, value: f64) -> ComparisonReport {
+        self.thresholds.push((name.to_string(), value));
+        self
+    }
+}
+
+impl fmt::Debug for ComparisonReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let debug_image_names: Vec<&str> = self.debug_images.iter().map(|(name, _)| name.as_str()).collect();
+        f.debug_struct("ComparisonReport")
+            .field("comparator", &self.comparator)
+            .field("stats", &self.stats)
+            .field("summary", &self.summary)
+            .field("score", &self.score)
+            .field("thresholds", &self.thresholds)
+            .field("diff_image", &self.diff_image.as_ref().map(|image| image.dimensions()))
+            .field("debug_images", &debug_image_names)
+            .finish()
+    }
+}
+
+/// The outcome of an `ImageComparator` comparing two images.
+#[derive(Clone, Debug)]
+pub enum Verdict {
+    Match(ComparisonReport),
+    Mismatch(ComparisonReport)
+}
+
+impl Verdict {
+    /// Whether the images were considered a match.
+    pub fn is_match(&self) -> bool {
+        match self {
+            Verdict::Match(_) => true,
+            Verdict::Mismatch(_) => false
+        }
+    }
+
+    /// The details of the comparison, regardless of its outcome.
+    pub fn report(&self) -> &ComparisonReport {
+        match self {
+            Verdict::Match(report) | Verdict::Mismatch(report) => report
+        }
+    }
+
+    /// Converts the verdict into its report.
+    pub fn into_report(self) -> ComparisonReport {
+        match self {
+            Verdict::Match(report) | Verdict::Mismatch(report) => report
+        }
+    }
+
+    pub(crate) fn from_report(report: ComparisonReport) -> Verdict {
+        if report.stats.is_match() {
+            Verdict::Match(report)
+        } else {
+            Verdict::Mismatch(report)
+        }
+    }
+}
+
+fn allowed_differing_pixels(max_differing_pixels: Option<u64>, max_differing_percent: Option<f64>, total_pixels: u64) -> u64 {
+    let by_percent = max_differing_percent
+        .map(|percent| (total_pixels as f64 * percent / 100.0).floor() as u64);
+    match (max_differing_pixels, by_percent) {
+        (Some(count), Some(by_percent)) => count.min(by_percent),
+        (Some(count), None) => count,
+        (None, Some(by_percent)) => by_percent,
+        (None, None) => 0
+    }
+}
+
+fn add_differing_pixel_thresholds(mut report: ComparisonReport, max_differing_pixels: Option<u64>, max_differing_percent: Option<f64>) -> ComparisonReport {
+    if let Some(count) = max_differing_pixels {
+        report = report.with_threshold("max_differing_pixels", count as f64);
+    }
+    if let Some(percent) = max_differing_percent {
+        report = report.with_threshold("max_differing_percent", percent);
+    }
+    report
+}
+
+/// Limits on how far a captured screenshot may stray from its reference image
+/// while still being considered a match.
+///
+/// A pixel counts as differing if any of its channels differs from the same pixel
+/// in the reference image by more than `max_channel_delta`. The comparison passes if
+/// the number of differing pixels is within both `max_differing_pixels` and
+/// `max_differing_percent`. If neither limit is set, no differing pixels are allowed.
+///
+/// `Tolerance::exact()` (also the `Default`) allows no differences at all.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Tolerance {
+    pub max_channel_delta: u8,
+    pub max_differing_pixels: Option<u64>,
+    pub max_differing_percent: Option<f64>
+}
+
+impl Tolerance {
+    /// A tolerance which requires every channel of every pixel to match exactly.
+    pub fn exact() -> Tolerance {
+        Tolerance {
+            max_channel_delta: 0,
+            max_differing_pixels: None,
+            max_differing_percent: None
+        }
+    }
+
+    /// Allows each channel of a pixel to differ by up to `delta` levels before
+    /// the pixel is counted as differing.
+    pub fn with_max_channel_delta(self, delta: u8) -> Tolerance {
+        Tolerance { max_channel_delta: delta, ..self }
+    }
+
+    /// Allows up to `count` pixels to differ.
+    pub fn with_max_differing_pixels(self, count: u64) -> Tolerance {
+        Tolerance { max_differing_pixels: Some(count), ..self }
+    }
+
+    /// Allows up to `percent` percent (0.0 - 100.0) of the pixels in the image to differ.
+    pub fn with_max_differing_percent(self, percent: f64) -> Tolerance {
+        Tolerance { max_differing_percent: Some(percent), ..self }
+    }
+
+    /// Records the limits of this tolerance in `report`.
+    fn add_thresholds(&self, report: ComparisonReport) -> ComparisonReport {
+        add_differing_pixel_thresholds(
+            report.with_threshold("max_channel_delta", f64::from(self.max_channel_delta)),
+            self.max_differing_pixels,
+            self.max_differing_percent
+        )
+    }
+
+    /// The number of differing pixels allowed in an image of `total_pixels` pixels.
+    pub fn allowed_differing_pixels(&self, total_pixels: u64) -> u64 {
+        allowed_differing_pixels(self.max_differing_pixels, self.max_differing_percent, total_pixels)
+    }
+}
+
+impl Default for Tolerance {
+    fn default() -> Tolerance {
+        Tolerance::exact()
+    }
+}
+
+/// Describes how many pixels of a captured screenshot differed from its reference image,
+/// and how many were allowed to differ.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct DiffStats {
+    /// The number of pixels compared. If the images differ in size, this covers the
+    /// area of both images and any pixel missing from one of them counts as differing.
+    pub total_pixels: u64,
+    /// The number of pixels which the comparator considered to differ.
+    pub differing_pixels: u64,
+    /// The number of differing pixels allowed by the comparator.
+    pub allowed_differing_pixels: u64,
+    /// The largest difference seen in any channel of any pixel.
+    pub max_channel_delta: u8,
+    /// The number of pixels which differed but were deliberately not counted, such as anti-aliased pixels.
+    pub ignored_pixels: u64
+}
+
+impl DiffStats {
+    /// Whether the number of differing pixels is within the allowed limit.
+    pub fn is_match(&self) -> bool {
+        self.differing_pixels <= self.allowed_differing_pixels
+    }
+
+    /// The number of differing pixels beyond those allowed.
+    pub fn excess_pixels(&self) -> u64 {
+        self.differing_pixels.saturating_sub(self.allowed_differing_pixels)
+    }
+
+    /// The percentage of compared pixels which differ.
+    pub fn differing_percent(&self) -> f64 {
+        if self.total_pixels == 0 {
+            0.0
+        } else {
+            self.differing_pixels as f64 * 100.0 / self.total_pixels as f64
+        }
+    }
+}
+
+impl fmt::Display for DiffStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} of {} pixels ({:.2}%) differ, {} over the limit of {}. Largest channel difference: {}.",
+            self.differing_pixels, self.total_pixels, self.differing_percent(),
+            self.excess_pixels(), self.allowed_differing_pixels, self.max_channel_delta)?;
+        if self.ignored_pixels > 0 {
+            write!(f, " {} differing pixels were ignored.", self.ignored_pixels)?;
+        }
+        Ok(())
+    }
+}
+
+pub(crate) fn channel_delta(actual: Rgba<u8>, expected: Rgba<u8>) -> u8 {
+    actual.data.iter().zip(expected.data.iter())
+        .map(|(a, e)| a.abs_diff(*e))
+        .max()
+        .unwrap_or(0)
+}
+
+/// Visits every pixel in the area covered by either image, passing the pixel from each image
+/// if both images contain it, or `None` if only one does. Returns the number of pixels visited.
+pub(crate) fn for_each_pixel_pair<F: FnMut(u32, u32, Option<(Rgba<u8>, Rgba<u8>)>)>(actual: &RgbaImage, expected: &RgbaImage, mut f: F) -> u64 {
+    let width = actual.width().max(expected.width());
+    let height = actual.height().max(expected.height());
+    for y in 0..height {
+        for x in 0..width {
+            match (actual.in_bounds(x, y), expected.in_bounds(x, y)) {
+                (true, true) => f(x, y, Some((*actual.get_pixel(x, y), *expected.get_pixel(x, y)))),
+                (false, false) => {},
+                _ => f(x, y, None)
+            }
+        }
+    }
+    u64::from(width) * u64::from(height)
+}
+
+/// Compares two images channel by channel and counts the pixels which differ by more than
+/// `tolerance` allows.
+///
+/// Both images are converted to RGBA before comparison. If the images are different sizes,
+/// any pixel present in only one of the images is considered to differ by the maximum amount.
+pub fn compare_images(actual: &DynamicImage, expected: &DynamicImage, tolerance: &Tolerance) -> DiffStats {
+    let mut differing_pixels = 0;
+    let mut max_channel_delta = 0;
+    let total_pixels = for_each_pixel_pair(&actual.to_rgba(), &expected.to_rgba(), |_, _, pixels| {
+        let delta = pixels.map_or(u8::MAX, |(a, e)| channel_delta(a, e));
+        max_channel_delta = max_channel_delta.max(delta);
+        if delta > tolerance.max_channel_delta {
+            differing_pixels += 1;
+        }
+    });
+
+    DiffStats {
+        total_pixels,
+        differing_pixels,
+        allowed_differing_pixels: tolerance.allowed_differing_pixels(total_pixels),
+        max_channel_delta,
+        ignored_pixels: 0
+    }
+}
+
+/// Requires every channel of every pixel to match the reference image exactly.
+///
+/// This is the comparator used by `screenshot_test` and `gl_screenshot_test`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ExactComparator;
+
+impl ImageComparator for ExactComparator {
+    fn compare(&self, actual: &DynamicImage, expected: &DynamicImage) -> Verdict {
+        let stats = compare_images(actual, expected, &Tolerance::exact());
+        let summary = stats.to_string();
+        Verdict::from_report(Tolerance::exact().add_thresholds(ComparisonReport::new("exact", stats, summary)))
+    }
+}
+
+/// Allows pixels to differ within the limits of a `Tolerance`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ToleranceComparator {
+    pub tolerance: Tolerance
+}
+
+impl ToleranceComparator {
+    pub fn new(tolerance: Tolerance) -> ToleranceComparator {
+        ToleranceComparator { tolerance }
+    }
+}
+
+impl ImageComparator for ToleranceComparator {
+    fn compare(&self, actual: &DynamicImage, expected: &DynamicImage) -> Verdict {
+        let stats = compare_images(actual, expected, &self.tolerance);
+        let summary = format!("Allowing channel differences of up to {}: {}", self.tolerance.max_channel_delta, stats);
+        Verdict::from_report(self.tolerance.add_thresholds(ComparisonReport::new("tolerance", stats, summary)))
+    }
+}
+
+/// The largest possible value of `yiq_delta`, between black and white.
+const MAX_YIQ_DELTA: f64 = 35215.0;
+
+fn blend_with_white(channel: u8, alpha: f64) -> f64 {
+    255.0 + (f64::from(channel) - 255.0) * alpha
+}
+
+pub(crate) fn rgb_to_yiq(pixel: Rgba<u8>) -> (f64, f64, f64) {
+    let alpha = f64::from(pixel.data[3]) / 255.0;
+    let r = blend_with_white(pixel.data[0], alpha);
+    let g = blend_with_white(pixel.data[1], alpha);
+    let b = blend_with_white(pixel.data[2], alpha);
+    (
+        r * 0.298_895_31 + g * 0.586_622_47 + b * 0.114_482_23,
+        r * 0.595_977_99 - g * 0.274_176_10 - b * 0.321_801_89,
+        r * 0.211_470_17 - g * 0.522_617_15 + b * 0.311_146_94
+    )
+}
+
+/// The squared perceptual distance between two colours in the YIQ colour space, as used by
+/// the pixelmatch library. Transparent pixels are blended with white before comparison.
+pub(crate) fn yiq_delta(actual: Rgba<u8>, expected: Rgba<u8>) -> f64 {
+    let (y1, i1, q1) = rgb_to_yiq(actual);
+    let (y2, i2, q2) = rgb_to_yiq(expected);
+    let (y, i, q) = (y1 - y2, i1 - i2, q1 - q2);
+    0.5053 * y * y + 0.299 * i * i + 0.1957 * q * q
+}
+
+/// Whether the pixel at (x, y) has more than two identical neighbours, suggesting it is part
+/// of a flat area of colour rather than an edge.
+fn has_many_siblings(image: &RgbaImage, x: u32, y: u32) -> bool {
+    let (width, height) = image.dimensions();
+    let pixel = image.get_pixel(x, y);
+    let on_edge = x == 0 || y == 0 || x == width - 1 || y == height - 1;
+    let mut identical = if on_edge { 1 } else { 0 };
+    for ny in y.saturating_sub(1)..(y + 2).min(height) {
+        for nx in x.saturating_sub(1)..(x + 2).min(width) {
+            if (nx, ny) != (x, y) && image.get_pixel(nx, ny) == pixel {
+                identical += 1;
+                if identical > 2 {
+                    return true;
+                }
+            }
+        }
+    }
+    false
+}
+
+/// Detects whether the pixel at (x, y) of `image` is likely to be an anti-aliased edge, using the
+/// method from the pixelmatch library (itself based on "Anti-aliased Pixel and Intensity Slope Detector"
+/// by Vysniauskas, 2009).
+///
+/// A pixel is considered anti-aliased if it lies between a darker and a brighter neighbour, and either
+/// of those neighbours lies within a flat area of colour in both images.
+fn is_anti_aliased(image: &RgbaImage, other: &RgbaImage, x: u32, y: u32) -> bool {
+    let (width, height) = image.dimensions();
+    let brightness = rgb_to_yiq(*image.get_pixel(x, y)).0;
+    let on_edge = x == 0 || y == 0 || x == width - 1 || y == height - 1;
+    let mut identical = if on_edge { 1 } else { 0 };
+    let mut darkest: Option<(f64, u32, u32)> = None;
+    let mut brightest: Option<(f64, u32, u32)> = None;
+    for ny in y.saturating_sub(1)..(y + 2).min(height) {
+        for nx in x.saturating_sub(1)..(x + 2).min(width) {
+            if (nx, ny) == (x, y) {
+                continue;
+            }
+            let delta = brightness - rgb_to_yiq(*image.get_pixel(nx, ny)).0;
+            if delta == 0.0 {
+                identical += 1;
+                if identical > 2 {
+                    return false;
+                }
+            } else if delta < darkest.map_or(0.0, |(d, _, _)| d) {
+                darkest = Some((delta, nx, ny));
+            } else if delta > brightest.map_or(0.0, |(d, _, _)| d) {
+                brightest = Some((delta, nx, ny));
+            }
+        }
+    }
+    match (darkest, brightest) {
+        (Some((_, dx, dy)), Some((_, bx, by))) => {
+            (has_many_siblings(image, dx, dy) && has_many_siblings(other, dx, dy))
+                || (has_many_siblings(image, bx, by) && has_many_siblings(other, bx, by))
+        },
+        _ => false
+    }
+}
+
+/// Compares pixels by their perceived colour difference, measured in the YIQ colour space.
+///
+/// Differences in brightness are weighted more heavily than differences in hue, so small
+/// shifts in colour that are hard to see are less likely to fail a test than a raw channel comparison.
+///
+/// `threshold` ranges from 0.0 (any difference counts) to 1.0 (only the difference between black
+/// and white counts), defaulting to 0.1. As with `Tolerance`, a limited number of differing pixels
+/// may be allowed using `max_differing_pixels` and `max_differing_percent`.
+///
+/// If `ignore_anti_aliasing` is set, differing pixels which look like anti-aliased edges in either
+/// image are not counted, in the same way as the pixelmatch library. This avoids failures caused by
+/// rasterisation differences on the edges of rotated or scaled sprites. The diff image then shows
+/// ignored pixels in `anti_aliased_colour` (yellow by default), while counted pixels are copied
+/// from the actual screenshot as usual.
+#[derive(Clone, Copy, Debug)]
+pub struct PerceptualComparator {
+    pub threshold: f64,
+    pub max_differing_pixels: Option<u64>,
+    pub max_differing_percent: Option<f64>,
+    pub ignore_anti_aliasing: bool,
+    pub anti_aliased_colour: Rgba<u8>
+}
+
+impl PerceptualComparator {
+    pub fn new(threshold: f64) -> PerceptualComparator {
+        PerceptualComparator {
+            threshold,
+            max_differing_pixels: None,
+            max_differing_percent: None,
+            ignore_anti_aliasing: false,
+            anti_aliased_colour: Rgba { data: [255, 255, 0, 255] }
+        }
+    }
+
+    /// Allows up to `count` pixels to differ.
+    pub fn with_max_differing_pixels(self, count: u64) -> PerceptualComparator {
+        PerceptualComparator { max_differing_pixels: Some(count), ..self }
+    }
+
+    /// Allows up to `percent` percent (0.0 - 100.0) of the pixels in the image to differ.
+    pub fn with_max_differing_percent(self, percent: f64) -> PerceptualComparator {
+        PerceptualComparator { max_differing_percent: Some(percent), ..self }
+    }
+
+    /// Whether differing pixels which appear to be anti-aliased edges should be ignored.
+    pub fn with_ignore_anti_aliasing(self, ignore_anti_aliasing: bool) -> PerceptualComparator {
+        PerceptualComparator { ignore_anti_aliasing, ..self }
+    }
+
+    /// The colour used to mark ignored anti-aliased pixels in the diff image.
+    pub fn with_anti_aliased_colour(self, colour: Rgba<u8>) -> PerceptualComparator {
+        PerceptualComparator { anti_aliased_colour: colour, ..self }
+    }
+}
+
+impl Default for PerceptualComparator {
+    fn default() -> PerceptualComparator {
+        PerceptualComparator::new(0.1)
+    }
+}
+
+impl ImageComparator for PerceptualComparator {
+    fn compare(&self, actual: &DynamicImage, expected: &DynamicImage) -> Verdict {
+        let max_delta = MAX_YIQ_DELTA * self.threshold * self.threshold;
+        let actual = actual.to_rgba();
+        let expected = expected.to_rgba();
+        let detect_anti_aliasing = self.ignore_anti_aliasing && actual.dimensions() == expected.dimensions();
+        let mut diff = RgbaImage::new(actual.width(), actual.height());
+        let mut differing_pixels = 0;
+        let mut ignored_pixels = 0;
+        let mut max_channel_delta = 0;
+        let total_pixels = for_each_pixel_pair(&actual, &expected, |x, y, pixels| {
+            match pixels {
+                Some((a, e)) => {
+                    max_channel_delta = max_channel_delta.max(channel_delta(a, e));
+                    if yiq_delta(a, e) > max_delta {
+                        if detect_anti_aliasing && (is_anti_aliased(&actual, &expected, x, y) || is_anti_aliased(&expected, &actual, x, y)) {
+                            ignored_pixels += 1;
+                            diff.put_pixel(x, y, self.anti_aliased_colour);
+                        } else {
+                            differing_pixels += 1;
+                            diff.put_pixel(x, y, a);
+                        }
+                    }
+                },
+                None => {
+                    max_channel_delta = u8::MAX;
+                    differing_pixels += 1;
+                    if diff.in_bounds(x, y) {
+                        diff.put_pixel(x, y, *actual.get_pixel(x, y));
+                    }
+                }
+            }
+        });
+        let stats = DiffStats {
+            total_pixels,
+            differing_pixels,
+            allowed_differing_pixels: allowed_differing_pixels(self.max_differing_pixels, self.max_differing_percent, total_pixels),
+            max_channel_delta,
+            ignored_pixels
+        };
+        let summary = format!("Using a perceptual threshold of {}: {}", self.threshold, stats);
+        let mut report = add_differing_pixel_thresholds(
+            ComparisonReport::new("perceptual", stats, summary).with_threshold("threshold", self.threshold),
+            self.max_differing_pixels,
+            self.max_differing_percent
+        );
+        if self.ignore_anti_aliasing {
+            report.diff_image = Some(DynamicImage::ImageRgba8(diff));
+        }
+        Verdict::from_report(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tests::{rgbw, rbgw, rgbw_off_by};
+
+    #[test]
+    fn test_compare_images_counts_pixels_over_channel_delta() {
+        let stats = compare_images(&rgbw_off_by(2), &rgbw(), &Tolerance::exact().with_max_channel_delta(1));
+        assert_eq!(stats.differing_pixels, 2);
+        assert_eq!(stats.max_channel_delta, 2);
+        assert_eq!(stats.excess_pixels(), 2);
+        assert!(!stats.is_match());
+
+        let stats = compare_images(&rgbw_off_by(2), &rgbw(), &Tolerance::exact().with_max_channel_delta(2));
+        assert_eq!(stats.differing_pixels, 0);
+        assert!(stats.is_match());
+    }
+
+    #[test]
+    fn test_compare_images_differing_pixel_limits() {
+        let tolerance = Tolerance::exact().with_max_differing_pixels(1);
+        let stats = compare_images(&rbgw(), &rgbw(), &tolerance);
+        assert_eq!(stats.allowed_differing_pixels, 1);
+        assert_eq!(stats.excess_pixels(), 1);
+
+        let tolerance = Tolerance::exact().with_max_differing_percent(50.0);
+        assert!(compare_images(&rbgw(), &rgbw(), &tolerance).is_match());
+
+        let tolerance = Tolerance::exact().with_max_differing_pixels(2).with_max_differing_percent(25.0);
+        assert_eq!(compare_images(&rbgw(), &rgbw(), &tolerance).allowed_differing_pixels, 1);
+    }
+
+    #[test]
+    fn test_compare_images_different_sizes() {
+        let stats = compare_images(&DynamicImage::new_rgba8(2, 1), &DynamicImage::new_rgba8(1, 2), &Tolerance::exact());
+        assert_eq!(stats.total_pixels, 4);
+        assert_eq!(stats.differing_pixels, 2);
+        assert_eq!(stats.max_channel_delta, 255);
+    }
+
+    #[test]
+    fn test_exact_comparator() {
+        assert!(ExactComparator.compare(&rgbw(), &rgbw()).is_match());
+        let verdict = ExactComparator.compare(&rgbw_off_by(1), &rgbw());
+        assert!(!verdict.is_match());
+        assert_eq!(verdict.report().comparator, "exact");
+        assert_eq!(verdict.report().stats.differing_pixels, 2);
+        assert_eq!(verdict.report().thresholds, vec![("max_channel_delta".to_string(), 0.0)]);
+    }
+
+    #[test]
+    fn test_perceptual_comparator_ignores_small_colour_shifts() {
+        assert!(PerceptualComparator::default().compare(&rgbw_off_by(3), &rgbw()).is_match());
+        let verdict = PerceptualComparator::default().compare(&rbgw(), &rgbw());
+        assert!(!verdict.is_match());
+        assert_eq!(verdict.report().stats.differing_pixels, 2);
+        assert!(PerceptualComparator::default().with_max_differing_pixels(2).compare(&rbgw(), &rgbw()).is_match());
+    }
+
+    /// A black square on white with a one pixel wide grey anti-aliased right edge,
+    /// offset by `shift` pixels to the right.
+    fn square_with_soft_edge(shift: u32) -> DynamicImage {
+        DynamicImage::ImageRgba8(ImageBuffer::from_fn(12, 12, |x, y| {
+            let edge = 6 + shift;
+            let level = if !(2..=9).contains(&y) || x > edge {
+                255
+            } else if x == edge {
+                128
+            } else {
+                0
+            };
+            Rgba { data: [level, level, level, 255] }
+        }))
+    }
+
+    #[test]
+    fn test_perceptual_comparator_ignores_anti_aliasing() {
+        let comparator = PerceptualComparator::default();
+        let verdict = comparator.compare(&square_with_soft_edge(1), &square_with_soft_edge(0));
+        assert!(!verdict.is_match());
+        assert!(verdict.report().diff_image.is_none());
+
+        let comparator = comparator.with_ignore_anti_aliasing(true);
+        let verdict = comparator.compare(&square_with_soft_edge(1), &square_with_soft_edge(0));
+        let report = verdict.report();
+        assert!(report.stats.ignored_pixels > 0);
+        let diff = report.diff_image.as_ref().unwrap().to_rgba();
+        assert_eq!(*diff.get_pixel(6, 5), comparator.anti_aliased_colour);
+        assert_eq!(*diff.get_pixel(0, 0), Rgba { data: [0, 0, 0, 0] });
+        assert_eq!(report.stats.differing_pixels, 0);
+        match verdict {
+            Verdict::Match(_) => {},
+            Verdict::Mismatch(report) => panic!("Expected the shifted edge to match: {}", report.summary)
+        }
+
+        // A stripe of colour along the same edge is a real change, not anti-aliasing.
+        let mut striped = square_with_soft_edge(0).to_rgba();
+        for y in 2..10 {
+            for x in 6..8 {
+                striped.put_pixel(x, y, Rgba { data: [255, 0, 0, 255] });
+            }
+        }
+        let verdict = comparator.compare(&DynamicImage::ImageRgba8(striped), &square_with_soft_edge(0));
+        assert!(!verdict.is_match());
+        assert!(verdict.report().stats.differing_pixels > 0);
+    }
+
+    #[test]
+    fn test_perceptual_comparator_anti_aliasing_still_counts_real_changes() {
+        let comparator = PerceptualComparator::default().with_ignore_anti_aliasing(true);
+        let verdict = comparator.compare(&rbgw(), &rgbw());
+        assert!(!verdict.is_match());
+        assert_eq!(verdict.report().stats.differing_pixels, 2);
+    }
+}
@@ -0,0 +1,384 @@
+//! Suite-wide settings read from `xray.toml`, so a whole test suite can be tuned without changing each test.
+
+use std::collections::BTreeMap;
+use std::env;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use image::DynamicImage;
+use toml;
+
+use comparator::{ExactComparator, ImageComparator, PerceptualComparator, Tolerance, ToleranceComparator, Verdict};
+use mask::Region;
+use ssim::SsimComparator;
+use {UpdateMode, XrayError, XrayResult, DEFAULT_OUTPUT_PATH, DEFAULT_REFERENCES_PATH, UPDATE_MODE_ENV_VAR};
+
+/// The name of the configuration file, looked for at the root of the crate being tested.
+pub const CONFIG_FILE_NAME: &str = "xray.toml";
+
+/// The environment variable used to read the configuration from another file.
+pub const CONFIG_ENV_VAR: &str = "XRAY_CONFIG";
+
+/// The environment variable which overrides `Config::references`.
+pub const REFERENCES_PATH_ENV_VAR: &str = "XRAY_REFERENCES";
+
+/// The environment variable which overrides `Config::test_output`.
+pub const OUTPUT_PATH_ENV_VAR: &str = "XRAY_TEST_OUTPUT";
+
+/// The environment variable which overrides the kind of the default comparator, e.g. `XRAY_COMPARATOR=perceptual`.
+pub const COMPARATOR_ENV_VAR: &str = "XRAY_COMPARATOR";
+
+/// The environment variable which overrides `Config::variants`, as a comma separated list, e.g. `XRAY_VARIANTS=llvmpipe,linux`.
+pub const VARIANTS_ENV_VAR: &str = "XRAY_VARIANTS";
+
+/// Settings for all the screenshot tests of a crate, usually read from `xray.toml` by `Config::load`.
+///
+/// ```toml
+/// references = "tests/references"
+/// test_output = "target/test_output"
+/// update = "new"
+/// variants = ["llvmpipe", "linux"]
+///
+/// [comparator]
+/// kind = "tolerance"
+/// max_channel_delta = 2
+/// max_differing_percent = 0.1
+///
+/// [tests."basic_rendering/initial_map"]
+/// ignore = [{ x = 1200, y = 0, width = 80, height = 20 }]
+///
+/// [tests."basic_rendering/particles".comparator]
+/// kind = "ssim"
+/// threshold = 0.95
+/// ```
+///
+/// Every setting is optional. `ScreenshotIo::default` reads and writes images in the configured
+/// directories with the configured update mode and reference variants, and `ScreenshotTest::new` compares
+/// screenshots with the comparator and ignore regions configured for the test, unless the test chooses its own.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// The directory containing reference images. Defaults to `references`.
+    pub references: PathBuf,
+    /// The directory to which the output of tests is written. Defaults to `test_output`.
+    pub test_output: PathBuf,
+    /// The update mode, `off`, `new` or `all`. When unset, it is read from the `XRAY_UPDATE` environment variable.
+    pub update: Option<UpdateMode>,
+    /// The keys of the reference variants to look for, most specific first (see `FsScreenshotIo::with_variant_keys`).
+    /// When unset, they are detected with `detect_variant_keys`.
+    pub variants: Option<Vec<String>>,
+    /// The comparator used by tests which do not configure their own.
+    pub comparator: ComparatorConfig,
+    /// Settings for individual tests, by test name.
+    pub tests: BTreeMap<String, TestConfig>
+}
+
+/// Settings for a single test, in the `[tests."<test name>"]` table of `xray.toml`.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TestConfig {
+    /// Regions of the screenshot to leave out of the comparison, as `MaskedComparator::with_region` does.
+    pub ignore: Vec<Region>,
+    /// Replaces the default comparator for this test.
+    pub comparator: Option<ComparatorConfig>
+}
+
+/// Which of the built in comparators a `ComparatorConfig` uses.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ComparatorKind {
+    /// `ExactComparator`. This is the default.
+    #[default]
+    Exact,
+    /// `ToleranceComparator`.
+    Tolerance,
+    /// `PerceptualComparator`.
+    Perceptual,
+    /// `SsimComparator`.
+    Ssim
+}
+
+impl ComparatorKind {
+    fn parse(value: &str) -> Option<ComparatorKind> {
+        match value.trim().to_lowercase().as_str() {
+            "exact" => Some(ComparatorKind::Exact),
+            "tolerance" => Some(ComparatorKind::Tolerance),
+            "perceptual" => Some(ComparatorKind::Perceptual),
+            "ssim" => Some(ComparatorKind::Ssim),
+            _ => None
+        }
+    }
+}
+
+/// A comparator and its thresholds, as configured in `xray.toml`. It compares images using the
+/// built in comparator selected by `kind`.
+///
+/// Settings which do not apply to the selected comparator are ignored, and unset settings take the
+/// comparator's defaults.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ComparatorConfig {
+    pub kind: ComparatorKind,
+    /// See `Tolerance::max_channel_delta`.
+    pub max_channel_delta: u8,
+    /// See `Tolerance::max_differing_pixels` and `PerceptualComparator::max_differing_pixels`.
+    pub max_differing_pixels: Option<u64>,
+    /// See `Tolerance::max_differing_percent` and `PerceptualComparator::max_differing_percent`.
+    pub max_differing_percent: Option<f64>,
+    /// The threshold of `PerceptualComparator` or `SsimComparator`.
+    pub threshold: Option<f64>,
+    /// See `PerceptualComparator::ignore_anti_aliasing`.
+    pub ignore_anti_aliasing: bool,
+    /// See `SsimComparator::window_size`.
+    pub window_size: Option<u32>,
+    /// See `SsimComparator::multi_scale`.
+    pub multi_scale: bool
+}
+
+impl ComparatorConfig {
+    fn tolerance(&self) -> Tolerance {
+        Tolerance {
+            max_channel_delta: self.max_channel_delta,
+            max_differing_pixels: self.max_differing_pixels,
+            max_differing_percent: self.max_differing_percent
+        }
+    }
+
+    fn perceptual(&self) -> PerceptualComparator {
+        let defaults = PerceptualComparator::default();
+        PerceptualComparator {
+            threshold: self.threshold.unwrap_or(defaults.threshold),
+            max_differing_pixels: self.max_differing_pixels,
+            max_differing_percent: self.max_differing_percent,
+            ignore_anti_aliasing: self.ignore_anti_aliasing,
+            ..defaults
+        }
+    }
+
+    fn ssim(&self) -> SsimComparator {
+        let defaults = SsimComparator::default();
+        let comparator = SsimComparator::new(self.threshold.unwrap_or(defaults.threshold)).with_multi_scale(self.multi_scale);
+        comparator.with_window_size(self.window_size.unwrap_or(defaults.window_size))
+    }
+}
+
+impl ImageComparator for ComparatorConfig {
+    fn compare(&self, actual: &DynamicImage, expected: &DynamicImage) -> Verdict {
+        match self.kind {
+            ComparatorKind::Exact => ExactComparator.compare(actual, expected),
+            ComparatorKind::Tolerance => ToleranceComparator::new(self.tolerance()).compare(actual, expected),
+            ComparatorKind::Perceptual => self.perceptual().compare(actual, expected),
+            ComparatorKind::Ssim => self.ssim().compare(actual, expected)
+        }
+    }
+}
+
+/// Returned when the configuration file can not be read, or contains invalid settings.
+#[derive(Clone, Debug)]
+pub struct ConfigError {
+    pub path: PathBuf,
+    pub reason: String
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Could not load xray configuration from {}: {}", self.path.display(), self.reason)
+    }
+}
+
+impl Error for ConfigError {}
+
+static GLOBAL_CONFIG: OnceLock<Result<Config, ConfigError>> = OnceLock::new();
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            references: PathBuf::from(DEFAULT_REFERENCES_PATH),
+            test_output: PathBuf::from(DEFAULT_OUTPUT_PATH),
+            update: None,
+            variants: None,
+            comparator: ComparatorConfig::default(),
+            tests: BTreeMap::new()
+        }
+    }
+}
+
+impl Config {
+    /// Parses a configuration file. Relative directories in it are resolved against the directory containing the file.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Config, ConfigError> {
+        let path = path.as_ref();
+        let error = |reason: String| ConfigError { path: path.to_owned(), reason };
+        let contents = fs::read_to_string(path).map_err(|err| error(err.to_string()))?;
+        let config: Config = toml::from_str(&contents).map_err(|err| error(err.to_string()))?;
+        let base = path.parent().unwrap_or_else(|| Path::new(""));
+        Ok(Config {
+            references: base.join(&config.references),
+            test_output: base.join(&config.test_output),
+            ..config
+        })
+    }
+
+    /// Loads the configuration for the crate being tested, then applies any overrides from environment variables.
+    ///
+    /// The configuration is read from the file named by `XRAY_CONFIG` if it is set, or otherwise
+    /// `xray.toml` in the crate's root directory (`CARGO_MANIFEST_DIR`, which cargo sets when running tests)
+    /// or the current directory. If there is no `xray.toml`, the defaults are used.
+    ///
+    /// `XRAY_REFERENCES`, `XRAY_TEST_OUTPUT`, `XRAY_UPDATE`, `XRAY_VARIANTS` and `XRAY_COMPARATOR` override the
+    /// `references`, `test_output`, `update` and `variants` settings and the kind of the default comparator.
+    pub fn load() -> Result<Config, ConfigError> {
+        let config = match env::var_os(CONFIG_ENV_VAR) {
+            Some(path) => Config::from_file(path)?,
+            None => {
+                let root = env::var_os("CARGO_MANIFEST_DIR").map(PathBuf::from).unwrap_or_default();
+                let path = root.join(CONFIG_FILE_NAME);
+                if path.exists() { Config::from_file(path)? } else { Config::default() }
+            }
+        };
+        config.with_overrides(|name| env::var(name).ok())
+    }
+
+    /// The configuration loaded by `Config::load` the first time it is called, which is used by
+    /// `ScreenshotIo::default` and `ScreenshotTest::new`.
+    ///
+    /// If the configuration could not be loaded, every call returns the error as `XrayError::Config`.
+    pub fn global() -> XrayResult<&'static Config> {
+        GLOBAL_CONFIG.get_or_init(Config::load).as_ref().map_err(|err| XrayError::Config(err.clone()))
+    }
+
+    /// Applies overrides from the environment variables, as looked up by `var`.
+    fn with_overrides<F: Fn(&str) -> Option<String>>(self, var: F) -> Result<Config, ConfigError> {
+        let mut config = self;
+        if let Some(references) = var(REFERENCES_PATH_ENV_VAR) {
+            config.references = PathBuf::from(references);
+        }
+        if let Some(test_output) = var(OUTPUT_PATH_ENV_VAR) {
+            config.test_output = PathBuf::from(test_output);
+        }
+        if let Some(update) = var(UPDATE_MODE_ENV_VAR) {
+            config.update = Some(UpdateMode::parse(&update));
+        }
+        if let Some(variants) = var(VARIANTS_ENV_VAR) {
+            config.variants = Some(variants.split(',').map(str::trim).filter(|key| !key.is_empty()).map(str::to_string).collect());
+        }
+        if let Some(kind) = var(COMPARATOR_ENV_VAR) {
+            config.comparator.kind = ComparatorKind::parse(&kind).ok_or_else(|| ConfigError {
+                path: PathBuf::from(COMPARATOR_ENV_VAR),
+                reason: format!("unknown comparator {:?}, expected exact, tolerance, perceptual or ssim", kind)
+            })?;
+        }
+        Ok(config)
+    }
+
+    /// The update mode, or `UpdateMode::Off` if none is configured.
+    pub fn update_mode(&self) -> UpdateMode {
+        self.update.unwrap_or_default()
+    }
+
+    /// The comparator configured for `test_name`, or the default comparator.
+    pub fn comparator(&self, test_name: &str) -> ComparatorConfig {
+        self.tests.get(test_name).and_then(|test| test.comparator).unwrap_or(self.comparator)
+    }
+
+    /// The regions configured to be ignored for `test_name`.
+    pub fn ignore_regions(&self, test_name: &str) -> &[Region] {
+        self.tests.get(test_name).map(|test| test.ignore.as_slice()).unwrap_or(&[])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::process;
+    use tests::{rbgw, rgbw, rgbw_off_by};
+
+    fn parse(contents: &str) -> Config {
+        toml::from_str(contents).unwrap()
+    }
+
+    #[test]
+    fn test_parse_config() {
+        let config = parse(r#"
+            references = "tests/references"
+            update = "new"
+
+            [comparator]
+            kind = "tolerance"
+            max_channel_delta = 2
+
+            [tests."menus/main"]
+            ignore = [{ x = 1, y = 0, width = 1, height = 1 }]
+
+            [tests."menus/main".comparator]
+            kind = "ssim"
+            threshold = 0.9
+        "#);
+        assert_eq!(config.references, PathBuf::from("tests/references"));
+        assert_eq!(config.test_output, PathBuf::from(DEFAULT_OUTPUT_PATH));
+        assert_eq!(config.update_mode(), UpdateMode::New);
+        assert_eq!(config.comparator("other").kind, ComparatorKind::Tolerance);
+        assert_eq!(config.comparator("menus/main").kind, ComparatorKind::Ssim);
+        assert_eq!(config.comparator("menus/main").ssim().threshold, 0.9);
+        assert_eq!(config.ignore_regions("menus/main"), &[Region::new(1, 0, 1, 1)]);
+        assert!(config.ignore_regions("other").is_empty());
+
+        assert!(toml::from_str::<Config>("refrences = \"typo\"").is_err());
+        assert!(toml::from_str::<Config>("[comparator]\nkind = \"fuzzy\"").is_err());
+        assert_eq!(parse(""), Config::default());
+    }
+
+    #[test]
+    fn test_env_overrides() {
+        let vars: HashMap<&str, &str> = [
+            (REFERENCES_PATH_ENV_VAR, "refs"),
+            (UPDATE_MODE_ENV_VAR, "all"),
+            (VARIANTS_ENV_VAR, "llvmpipe, linux"),
+            (COMPARATOR_ENV_VAR, "Perceptual")
+        ].iter().cloned().collect();
+        let config = parse("update = \"new\"").with_overrides(|name| vars.get(name).map(|value| value.to_string())).unwrap();
+        assert_eq!(config.references, PathBuf::from("refs"));
+        assert_eq!(config.test_output, PathBuf::from(DEFAULT_OUTPUT_PATH));
+        assert_eq!(config.update_mode(), UpdateMode::All);
+        assert_eq!(config.variants, Some(vec!["llvmpipe".to_string(), "linux".to_string()]));
+        assert_eq!(config.comparator.kind, ComparatorKind::Perceptual);
+
+        let err = Config::default().with_overrides(|_| Some("fuzzy".to_string())).unwrap_err();
+        assert!(err.to_string().contains("unknown comparator \"fuzzy\""));
+    }
+
+    #[test]
+    fn test_config_file_paths() {
+        let dir = env::temp_dir().join(format!("xray-config-test-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join(CONFIG_FILE_NAME), "test_output = \"target/output\"").unwrap();
+        let config = Config::from_file(dir.join(CONFIG_FILE_NAME)).unwrap();
+        assert_eq!(config.references, dir.join(DEFAULT_REFERENCES_PATH));
+        assert_eq!(config.test_output, dir.join("target/output"));
+
+        let err = Config::from_file(dir.join("missing.toml")).unwrap_err();
+        assert_eq!(err.path, dir.join("missing.toml"));
+        let err = XrayError::from(err);
+        assert!(err.to_string().starts_with("Could not load xray configuration from "));
+        assert!(Config::global().is_ok());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_configured_comparators() {
+        let exact = ComparatorConfig::default();
+        assert!(!exact.compare(&rgbw_off_by(2), &rgbw()).is_match());
+        let tolerance = ComparatorConfig { kind: ComparatorKind::Tolerance, max_channel_delta: 2, ..exact };
+        assert!(tolerance.compare(&rgbw_off_by(2), &rgbw()).is_match());
+        assert!(!tolerance.compare(&rbgw(), &rgbw()).is_match());
+        let perceptual = ComparatorConfig { kind: ComparatorKind::Perceptual, max_differing_pixels: Some(2), ..exact };
+        assert_eq!(perceptual.compare(&rbgw(), &rgbw()).report().comparator, "perceptual");
+        assert!(perceptual.compare(&rbgw(), &rgbw()).is_match());
+        let ssim = ComparatorConfig { kind: ComparatorKind::Ssim, multi_scale: true, ..exact };
+        assert_eq!(ssim.ssim().threshold, SsimComparator::default().threshold);
+        assert_eq!(ssim.compare(&rgbw(), &rgbw()).report().comparator, "ms-ssim");
+    }
+}
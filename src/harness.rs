@@ -0,0 +1,156 @@
+//! Helpers for running GL screenshot tests under cargo's parallel test runner.
+
+use std::sync::{Mutex, MutexGuard};
+
+use {FsScreenshotIo, ScreenshotIo};
+#[cfg(feature = "gl")]
+use OpenGlScreenshotCaptor;
+
+#[cfg(feature = "headless")]
+use std::cell::RefCell;
+
+#[cfg(feature = "headless")]
+use gl;
+#[cfg(feature = "headless")]
+use headless::{ContextError, HeadlessContext};
+
+static GL_LOCK: Mutex<()> = Mutex::new(());
+
+/// Takes a process-wide lock for creating windows or GL contexts and capturing screenshots, which
+/// is released when the returned guard is dropped.
+///
+/// Tests which create their own windows can hold this lock from creating the window until the
+/// screenshot has been compared, so that they can run under cargo's default parallelism:
+///
+/// ```no_run
+/// # extern crate xray;
+/// # fn main() {
+/// let _lock = xray::gl_lock();
+/// // Create the window, render and take the screenshot...
+/// xray::gl_screenshot_test("basic_rendering/initial_map", 0, 0, 1280, 720);
+/// # }
+/// ```
+///
+/// The lock is still taken if a test panicked while holding it, as failed screenshot tests do.
+pub fn gl_lock() -> MutexGuard<'static, ()> {
+    GL_LOCK.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// The name of a test, and so the path of its reference image, for the function `function_name` in
+/// the module `module_path` (as returned by `module_path!()`).
+///
+/// This is the module path with `::` replaced by `/`, followed by the function name, e.g.
+/// `basic_rendering/menus/initial_map` for `fn initial_map` in the `menus` module of the
+/// `basic_rendering` integration test.
+pub fn test_name(module_path: &str, function_name: &str) -> String {
+    format!("{}/{}", module_path.replace("::", "/"), function_name)
+}
+
+/// Values which `#[xray::test]` creates for a test's arguments, given the name of the test.
+pub trait FromTestName {
+    fn from_test_name(test_name: &str) -> Self;
+}
+
+impl FromTestName for FsScreenshotIo {
+    /// Uses `ScreenshotIo::default`, reading `references/<test_name>.png`.
+    fn from_test_name(test_name: &str) -> FsScreenshotIo {
+        FsScreenshotIo::default(test_name)
+    }
+}
+
+#[cfg(feature = "gl")]
+impl FromTestName for OpenGlScreenshotCaptor {
+    fn from_test_name(_test_name: &str) -> OpenGlScreenshotCaptor {
+        OpenGlScreenshotCaptor::new()
+    }
+}
+
+#[cfg(feature = "headless")]
+thread_local! {
+    static HEADLESS_CONTEXT: RefCell<Option<HeadlessContext>> = const { RefCell::new(None) };
+}
+
+/// Runs `test` with a `HeadlessContext` of the given size current on this thread.
+///
+/// Each of cargo's test threads creates one context, the first time it is needed, and reuses it for
+/// later tests of the same size, so tests can run in parallel without sharing a context. Before
+/// `test` runs, the context's framebuffer is bound, the viewport set to cover it, and it is cleared to
+/// transparent black. Other GL state set by earlier tests on the same thread is kept.
+///
+/// ```no_run
+/// # extern crate xray;
+/// # fn main() {
+/// xray::with_headless_context(1280, 720, |context| {
+///     // Render the scene...
+///     xray::gl_screenshot_test("basic_rendering/initial_map", 0, 0, context.width(), context.height());
+/// }).unwrap();
+/// # }
+/// ```
+#[cfg(feature = "headless")]
+pub fn with_headless_context<F, R>(width: u32, height: u32, test: F) -> Result<R, ContextError>
+    where F: FnOnce(&HeadlessContext) -> R
+{
+    HEADLESS_CONTEXT.with(|cell| {
+        let mut context = cell.borrow_mut();
+        let reusable = context.as_ref().is_some_and(|context| context.width() == width && context.height() == height);
+        if !reusable {
+            // Drop the old context before creating its replacement, which becomes current.
+            *context = None;
+            *context = Some(HeadlessContext::new(width, height)?);
+        }
+        let context = context.as_ref().unwrap();
+        context.make_current()?;
+        unsafe {
+            gl::Viewport(0, 0, width as i32, height as i32);
+            gl::ClearColor(0.0, 0.0, 0.0, 0.0);
+            gl::Clear(gl::COLOR_BUFFER_BIT | gl::DEPTH_BUFFER_BIT | gl::STENCIL_BUFFER_BIT);
+        }
+        Ok(test(context))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn test_test_name() {
+        assert_eq!(test_name("basic_rendering", "initial_map"), "basic_rendering/initial_map");
+        assert_eq!(test_name("game::render::tests", "minimap"), "game/render/tests/minimap");
+    }
+
+    #[test]
+    fn test_gl_lock_survives_panics() {
+        let result = thread::spawn(|| {
+            let _lock = gl_lock();
+            panic!("screenshot test failed");
+        }).join();
+        assert!(result.is_err());
+        drop(gl_lock());
+    }
+
+    #[cfg(feature = "headless")]
+    #[test]
+    fn test_headless_context_per_thread() {
+        use {OpenGlScreenshotCaptor, Orientation, ScreenshotCaptor};
+
+        let threads: Vec<_> = (0..4u8).map(|index| thread::spawn(move || {
+            let colour = f32::from(index) / 4.0;
+            for _ in 0..3 {
+                let pixel = with_headless_context(4, 2, |context| unsafe {
+                    assert_eq!(OpenGlScreenshotCaptor::new().capture_image(0, 0, 1, 1).unwrap().raw_pixels(), vec![0, 0, 0, 0]);
+                    gl::ClearColor(colour, 0.0, 0.0, 1.0);
+                    gl::Clear(gl::COLOR_BUFFER_BIT);
+                    thread::yield_now();
+                    let captor = OpenGlScreenshotCaptor::new().with_orientation(Orientation::TopDown);
+                    captor.capture_image(0, 0, context.width(), context.height()).unwrap().to_rgba().get_pixel(3, 1).data
+                }).unwrap();
+                assert_eq!(pixel, [(index as f32 * 255.0 / 4.0).round() as u8, 0, 0, 255]);
+            }
+        })).collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+    }
+}
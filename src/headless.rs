@@ -0,0 +1,358 @@
+//! A headless OpenGL context for running `gl_screenshot_test` without a window or display server,
+//! using EGL on Mesa's surfaceless platform. Without a GPU, Mesa renders with its llvmpipe software driver.
+
+use std::error::Error;
+use std::ffi::CString;
+use std::fmt;
+use std::os::raw::{c_char, c_void};
+use std::ptr;
+use std::sync::Once;
+
+use gl;
+use gl::types::GLuint;
+
+type EglDisplay = *mut c_void;
+type EglConfig = *mut c_void;
+type EglContext = *mut c_void;
+type EglSurface = *mut c_void;
+type EglInt = i32;
+type EglBoolean = u32;
+type GetPlatformDisplay = extern "C" fn(u32, *mut c_void, *const EglInt) -> EglDisplay;
+
+const EGL_SUCCESS: EglInt = 0x3000;
+const EGL_NONE: EglInt = 0x3038;
+const EGL_ALPHA_SIZE: EglInt = 0x3021;
+const EGL_BLUE_SIZE: EglInt = 0x3022;
+const EGL_GREEN_SIZE: EglInt = 0x3023;
+const EGL_RED_SIZE: EglInt = 0x3024;
+const EGL_DEPTH_SIZE: EglInt = 0x3025;
+const EGL_STENCIL_SIZE: EglInt = 0x3026;
+const EGL_SURFACE_TYPE: EglInt = 0x3033;
+const EGL_RENDERABLE_TYPE: EglInt = 0x3040;
+const EGL_OPENGL_BIT: EglInt = 0x0008;
+const EGL_OPENGL_API: u32 = 0x30A2;
+const EGL_PLATFORM_SURFACELESS_MESA: u32 = 0x31DD;
+
+/// Loads the OpenGL functions the first time a context is created. EGL returns the same function
+/// pointers for every context, and `gl::load_with` must not run while another thread is calling them.
+static LOAD_GL: Once = Once::new();
+
+#[link(name = "EGL")]
+extern "C" {
+    fn eglGetError() -> EglInt;
+    fn eglGetDisplay(native_display: *mut c_void) -> EglDisplay;
+    fn eglGetProcAddress(name: *const c_char) -> *const c_void;
+    fn eglInitialize(display: EglDisplay, major: *mut EglInt, minor: *mut EglInt) -> EglBoolean;
+    fn eglBindAPI(api: u32) -> EglBoolean;
+    fn eglChooseConfig(
+        display: EglDisplay,
+        attributes: *const EglInt,
+        configs: *mut EglConfig,
+        config_size: EglInt,
+        config_count: *mut EglInt
+    ) -> EglBoolean;
+    fn eglCreateContext(display: EglDisplay, config: EglConfig, share_context: EglContext, attributes: *const EglInt) -> EglContext;
+    fn eglMakeCurrent(display: EglDisplay, draw: EglSurface, read: EglSurface, context: EglContext) -> EglBoolean;
+    fn eglDestroyContext(display: EglDisplay, context: EglContext) -> EglBoolean;
+}
+
+/// The reason a `HeadlessContext` could not be created or made current.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ContextError {
+    /// The call which failed, e.g. `eglInitialize`.
+    pub call: String,
+    /// The name of the EGL error, e.g. `EGL_NOT_INITIALIZED`, or a description of what went wrong.
+    pub reason: String
+}
+
+impl fmt::Display for ContextError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Could not create a headless OpenGL context: {} failed: {}", self.call, self.reason)
+    }
+}
+
+impl Error for ContextError {}
+
+/// The name of an `eglGetError` code, e.g. `EGL_BAD_DISPLAY`.
+fn egl_error_name(error_code: EglInt) -> String {
+    let name = match error_code {
+        0x3000 => "EGL_SUCCESS",
+        0x3001 => "EGL_NOT_INITIALIZED",
+        0x3002 => "EGL_BAD_ACCESS",
+        0x3003 => "EGL_BAD_ALLOC",
+        0x3004 => "EGL_BAD_ATTRIBUTE",
+        0x3005 => "EGL_BAD_CONFIG",
+        0x3006 => "EGL_BAD_CONTEXT",
+        0x3007 => "EGL_BAD_CURRENT_SURFACE",
+        0x3008 => "EGL_BAD_DISPLAY",
+        0x3009 => "EGL_BAD_MATCH",
+        0x300A => "EGL_BAD_NATIVE_PIXMAP",
+        0x300B => "EGL_BAD_NATIVE_WINDOW",
+        0x300C => "EGL_BAD_PARAMETER",
+        0x300D => "EGL_BAD_SURFACE",
+        0x300E => "EGL_CONTEXT_LOST",
+        _ => return format!("unknown EGL error 0x{:04X}", error_code)
+    };
+    name.to_string()
+}
+
+/// Builds the error for a failed EGL call from `eglGetError`.
+fn egl_error(call: &str) -> ContextError {
+    let error_code = unsafe { eglGetError() };
+    ContextError {
+        call: call.to_string(),
+        reason: if error_code == EGL_SUCCESS { "no error reported".to_string() } else { egl_error_name(error_code) }
+    }
+}
+
+unsafe fn get_proc_address(name: &str) -> *const c_void {
+    let name = CString::new(name).unwrap();
+    eglGetProcAddress(name.as_ptr())
+}
+
+/// Opens Mesa's surfaceless platform, which needs no display server, falling back to the default
+/// display if `EGL_MESA_platform_surfaceless` is not available.
+unsafe fn surfaceless_display() -> EglDisplay {
+    let get_platform_display = get_proc_address("eglGetPlatformDisplayEXT");
+    let display = if get_platform_display.is_null() {
+        ptr::null_mut()
+    } else {
+        let get_platform_display: GetPlatformDisplay = ::std::mem::transmute(get_platform_display);
+        get_platform_display(EGL_PLATFORM_SURFACELESS_MESA, ptr::null_mut(), [EGL_NONE].as_ptr())
+    };
+    if display.is_null() {
+        eglGetDisplay(ptr::null_mut())
+    } else {
+        display
+    }
+}
+
+/// An OpenGL context which renders into an offscreen framebuffer rather than a window, so that
+/// screenshot tests can run on machines without a GPU or display server, such as CI runners.
+///
+/// Creating the context makes it current on the calling thread, loads the `gl` function pointers,
+/// and binds a `width` x `height` framebuffer with RGBA8 colour and 24 bit depth and 8 bit stencil
+/// buffers for drawing and reading. Render into it as you would into a window, then capture it with
+/// `gl_screenshot_test` or `OpenGlScreenshotCaptor`.
+///
+/// ```no_run
+/// # extern crate xray;
+/// # fn main() {
+/// let context = xray::HeadlessContext::new(1280, 720).unwrap();
+/// // Render the scene...
+/// xray::gl_screenshot_test("basic_rendering/initial_map", 0, 0, context.width(), context.height());
+/// # }
+/// ```
+///
+/// The context is current only on the thread which created it. Like any GL context, it must be
+/// dropped on that thread.
+pub struct HeadlessContext {
+    display: EglDisplay,
+    context: EglContext,
+    framebuffer: GLuint,
+    renderbuffers: [GLuint; 2],
+    width: u32,
+    height: u32
+}
+
+impl HeadlessContext {
+    /// Creates a context with a framebuffer of the given size, and makes it current on this thread.
+    pub fn new(width: u32, height: u32) -> Result<HeadlessContext, ContextError> {
+        unsafe {
+            let display = surfaceless_display();
+            if display.is_null() {
+                return Err(egl_error("eglGetDisplay"));
+            }
+            let (mut major, mut minor) = (0, 0);
+            if eglInitialize(display, &mut major, &mut minor) == 0 {
+                return Err(egl_error("eglInitialize"));
+            }
+            if eglBindAPI(EGL_OPENGL_API) == 0 {
+                return Err(egl_error("eglBindAPI"));
+            }
+
+            let config_attributes = [
+                EGL_SURFACE_TYPE, 0,
+                EGL_RENDERABLE_TYPE, EGL_OPENGL_BIT,
+                EGL_RED_SIZE, 8,
+                EGL_GREEN_SIZE, 8,
+                EGL_BLUE_SIZE, 8,
+                EGL_ALPHA_SIZE, 8,
+                EGL_DEPTH_SIZE, 24,
+                EGL_STENCIL_SIZE, 8,
+                EGL_NONE
+            ];
+            let mut config = ptr::null_mut();
+            let mut config_count = 0;
+            if eglChooseConfig(display, config_attributes.as_ptr(), &mut config, 1, &mut config_count) == 0 {
+                return Err(egl_error("eglChooseConfig"));
+            }
+            if config_count == 0 {
+                return Err(ContextError {
+                    call: "eglChooseConfig".to_string(),
+                    reason: "no config supports OpenGL with RGBA8 colour and depth and stencil buffers".to_string()
+                });
+            }
+
+            let context = eglCreateContext(display, config, ptr::null_mut(), [EGL_NONE].as_ptr());
+            if context.is_null() {
+                return Err(egl_error("eglCreateContext"));
+            }
+            let mut headless_context = HeadlessContext {
+                display,
+                context,
+                framebuffer: 0,
+                renderbuffers: [0, 0],
+                width,
+                height
+            };
+            headless_context.make_current()?;
+            LOAD_GL.call_once(|| gl::load_with(|symbol| get_proc_address(symbol)));
+            headless_context.create_framebuffer()?;
+            Ok(headless_context)
+        }
+    }
+
+    /// Makes this context current on the calling thread, and binds its framebuffer.
+    pub fn make_current(&self) -> Result<(), ContextError> {
+        unsafe {
+            if eglMakeCurrent(self.display, ptr::null_mut(), ptr::null_mut(), self.context) == 0 {
+                return Err(egl_error("eglMakeCurrent"));
+            }
+            if self.framebuffer != 0 {
+                gl::BindFramebuffer(gl::FRAMEBUFFER, self.framebuffer);
+            }
+        }
+        Ok(())
+    }
+
+    /// The width of the framebuffer, in pixels.
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    /// The height of the framebuffer, in pixels.
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// The framebuffer object which is drawn into and read from instead of a window.
+    pub fn framebuffer(&self) -> u32 {
+        self.framebuffer
+    }
+
+    unsafe fn create_framebuffer(&mut self) -> Result<(), ContextError> {
+        let (width, height) = (self.width as i32, self.height as i32);
+        gl::GenRenderbuffers(2, self.renderbuffers.as_mut_ptr());
+        gl::BindRenderbuffer(gl::RENDERBUFFER, self.renderbuffers[0]);
+        gl::RenderbufferStorage(gl::RENDERBUFFER, gl::RGBA8, width, height);
+        gl::BindRenderbuffer(gl::RENDERBUFFER, self.renderbuffers[1]);
+        gl::RenderbufferStorage(gl::RENDERBUFFER, gl::DEPTH24_STENCIL8, width, height);
+        gl::BindRenderbuffer(gl::RENDERBUFFER, 0);
+
+        gl::GenFramebuffers(1, &mut self.framebuffer);
+        gl::BindFramebuffer(gl::FRAMEBUFFER, self.framebuffer);
+        gl::FramebufferRenderbuffer(gl::FRAMEBUFFER, gl::COLOR_ATTACHMENT0, gl::RENDERBUFFER, self.renderbuffers[0]);
+        gl::FramebufferRenderbuffer(gl::FRAMEBUFFER, gl::DEPTH_STENCIL_ATTACHMENT, gl::RENDERBUFFER, self.renderbuffers[1]);
+        let status = gl::CheckFramebufferStatus(gl::FRAMEBUFFER);
+        if status != gl::FRAMEBUFFER_COMPLETE {
+            return Err(ContextError {
+                call: "glCheckFramebufferStatus".to_string(),
+                reason: format!("framebuffer status 0x{:04X}", status)
+            });
+        }
+        gl::Viewport(0, 0, width, height);
+        Ok(())
+    }
+}
+
+impl Drop for HeadlessContext {
+    fn drop(&mut self) {
+        unsafe {
+            if self.make_current().is_ok() {
+                gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+                gl::DeleteFramebuffers(1, &self.framebuffer);
+                gl::DeleteRenderbuffers(2, self.renderbuffers.as_ptr());
+            }
+            eglMakeCurrent(self.display, ptr::null_mut(), ptr::null_mut(), ptr::null_mut());
+            // The display is left initialised, as terminating it would destroy the contexts of other threads.
+            eglDestroyContext(self.display, self.context);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use {CaptureBuffer, CaptureSource, OpenGlScreenshotCaptor, Orientation, ScreenshotCaptor};
+
+    /// Clears the whole framebuffer to blue at the far plane, then the bottom left quarter to red
+    /// at half depth.
+    unsafe fn draw_quadrants() {
+        gl::ClearColor(0.0, 0.0, 1.0, 1.0);
+        gl::ClearDepth(1.0);
+        gl::Clear(gl::COLOR_BUFFER_BIT | gl::DEPTH_BUFFER_BIT);
+        gl::Enable(gl::SCISSOR_TEST);
+        gl::Scissor(0, 0, 2, 2);
+        gl::ClearColor(1.0, 0.0, 0.0, 1.0);
+        gl::ClearDepth(0.5);
+        gl::Clear(gl::COLOR_BUFFER_BIT | gl::DEPTH_BUFFER_BIT);
+        gl::Disable(gl::SCISSOR_TEST);
+    }
+
+    fn pixel(image: &::image::DynamicImage, x: u32, y: u32) -> [u8; 4] {
+        image.to_rgba().get_pixel(x, y).data
+    }
+
+    #[test]
+    fn test_egl_error_names() {
+        assert_eq!(egl_error_name(0x3008), "EGL_BAD_DISPLAY");
+        assert_eq!(egl_error_name(0x4000), "unknown EGL error 0x4000");
+    }
+
+    #[test]
+    fn test_headless_capture() {
+        let context = HeadlessContext::new(4, 4).unwrap();
+        unsafe { draw_quadrants() };
+
+        let captor = OpenGlScreenshotCaptor::new().with_orientation(Orientation::TopDown);
+        let image = captor.capture_image(0, 0, context.width(), context.height()).unwrap();
+        assert_eq!(pixel(&image, 0, 3), [255, 0, 0, 255]);
+        assert_eq!(pixel(&image, 0, 0), [0, 0, 255, 255]);
+
+        let bottom_up = captor.clone().with_orientation(Orientation::BottomUp).capture_image(0, 0, 4, 4).unwrap();
+        assert_eq!(pixel(&bottom_up, 0, 0), [255, 0, 0, 255]);
+
+        let bound = captor.clone().with_source(CaptureSource::Framebuffer(context.framebuffer(), 0));
+        assert_eq!(pixel(&bound.capture_image(0, 0, 4, 4).unwrap(), 0, 3), [255, 0, 0, 255]);
+
+        let depth = captor.with_buffer(CaptureBuffer::Depth).capture_image(0, 0, 4, 4).unwrap();
+        assert_eq!(pixel(&depth, 0, 3), [255, 255, 255, 255]);
+        assert_eq!(pixel(&depth, 0, 0), [0, 0, 0, 255]);
+    }
+
+    #[test]
+    fn test_headless_capture_multisampled() {
+        let _context = HeadlessContext::new(4, 4).unwrap();
+        unsafe {
+            let mut renderbuffer = 0;
+            gl::GenRenderbuffers(1, &mut renderbuffer);
+            gl::BindRenderbuffer(gl::RENDERBUFFER, renderbuffer);
+            gl::RenderbufferStorageMultisample(gl::RENDERBUFFER, 4, gl::RGBA8, 4, 4);
+            let mut framebuffer = 0;
+            gl::GenFramebuffers(1, &mut framebuffer);
+            gl::BindFramebuffer(gl::FRAMEBUFFER, framebuffer);
+            gl::FramebufferRenderbuffer(gl::FRAMEBUFFER, gl::COLOR_ATTACHMENT0, gl::RENDERBUFFER, renderbuffer);
+            draw_quadrants();
+
+            let captor = OpenGlScreenshotCaptor::new().with_orientation(Orientation::TopDown);
+            let image = captor.capture_image(0, 0, 4, 4).unwrap();
+            assert_eq!(pixel(&image, 0, 3), [255, 0, 0, 255]);
+            assert_eq!(pixel(&image, 3, 0), [0, 0, 255, 255]);
+
+            let mut read_framebuffer = 0;
+            gl::GetIntegerv(gl::READ_FRAMEBUFFER_BINDING, &mut read_framebuffer);
+            assert_eq!(read_framebuffer as GLuint, framebuffer);
+        }
+    }
+}
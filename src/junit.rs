@@ -0,0 +1,140 @@
+//! A JUnit XML report of every screenshot test which wrote output to a directory, for CI systems
+//! such as GitLab which show JUnit test reports in merge requests.
+
+use std::io;
+use std::path::Path;
+use std::time::Duration;
+
+use report::{escape_html, find_test_records, write_report_file, TestRecord, TestStatus};
+
+/// The name of the JUnit report written to the top of the output directory.
+pub const JUNIT_FILE_NAME: &str = "junit.xml";
+
+/// Failure output images which are linked from the report as attachments, if they were written.
+const ATTACHED_IMAGES: [&str; 2] = ["actual.png", "diff.png"];
+
+/// Rebuilds `<output_path>/junit.xml` from the results recorded under `output_path`, with one
+/// test case for each test.
+///
+/// Failed tests include the error message, and link to their `actual.png` and `diff.png` using
+/// GitLab's `[[ATTACHMENT|<path>]]` syntax. Attachment paths start with `output_path`, so when
+/// it is relative the report should be read from the same directory the tests were run in.
+pub fn write_junit_report<P: AsRef<Path>>(output_path: P) -> io::Result<()> {
+    let output_path = output_path.as_ref();
+    let xml = render_junit_report(output_path, &find_test_records(output_path)?);
+    write_report_file(output_path, JUNIT_FILE_NAME, &xml)
+}
+
+/// The JUnit class name for a test, made from the directories in its name, e.g. `xray.menus` for `menus/main`.
+fn class_name(test_name: &str) -> String {
+    match test_name.rfind('/') {
+        Some(index) => format!("xray.{}", test_name[..index].replace('/', ".")),
+        None => "xray".to_string()
+    }
+}
+
+fn render_test_case(output_path: &Path, test_name: &str, record: &TestRecord) -> String {
+    let mut xml = format!(
+        "    <testcase classname=\"{}\" name=\"{}\" time=\"{:.3}\"",
+        escape_html(&class_name(test_name)),
+        escape_html(test_name),
+        record.timings.total.as_secs_f64()
+    );
+    let message = escape_html(record.summary.lines().next().unwrap_or(""));
+    let summary = escape_html(&record.summary);
+    let body = match record.status {
+        TestStatus::Failed | TestStatus::NoReference => format!(
+            "      <failure message=\"{}\" type=\"{}\">{}</failure>\n",
+            message, record.status.as_str(), summary
+        ),
+        TestStatus::Error => format!("      <error message=\"{}\" type=\"error\">{}</error>\n", message, summary),
+        TestStatus::Passed | TestStatus::Updated => String::new()
+    };
+
+    let attachments: Vec<String> = if record.status.is_failure() {
+        ATTACHED_IMAGES.iter()
+            .map(|image| output_path.join(test_name).join(image))
+            .filter(|path| path.is_file())
+            .map(|path| format!("[[ATTACHMENT|{}]]", escape_html(&path.to_string_lossy())))
+            .collect()
+    } else {
+        Vec::new()
+    };
+    let system_out = if record.status == TestStatus::Updated {
+        format!("      <system-out>{}</system-out>\n", summary)
+    } else if !attachments.is_empty() {
+        format!("      <system-out>{}</system-out>\n", attachments.join("\n"))
+    } else {
+        String::new()
+    };
+
+    if body.is_empty() && system_out.is_empty() {
+        xml.push_str("/>\n");
+    } else {
+        xml.push_str(&format!(">\n{}{}    </testcase>\n", body, system_out));
+    }
+    xml
+}
+
+fn render_junit_report(output_path: &Path, records: &[(String, TestRecord)]) -> String {
+    let count = |status: TestStatus| records.iter().filter(|(_, record)| record.status == status).count();
+    let failures = count(TestStatus::Failed) + count(TestStatus::NoReference);
+    let errors = count(TestStatus::Error);
+    let time: Duration = records.iter().map(|(_, record)| record.timings.total).sum();
+    let mut xml = format!(concat!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+        "<testsuites name=\"xray\" tests=\"{tests}\" failures=\"{failures}\" errors=\"{errors}\" time=\"{time:.3}\">\n",
+        "  <testsuite name=\"xray\" tests=\"{tests}\" failures=\"{failures}\" errors=\"{errors}\" skipped=\"0\" time=\"{time:.3}\">\n"),
+        tests = records.len(),
+        failures = failures,
+        errors = errors,
+        time = time.as_secs_f64()
+    );
+    for (test_name, record) in records {
+        xml.push_str(&render_test_case(output_path, test_name, record));
+    }
+    xml.push_str("  </testsuite>\n</testsuites>\n");
+    xml
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::process;
+    use report::RESULT_FILE_NAME;
+    use tests::rgbw;
+    use write_png_creating_dirs;
+
+    #[test]
+    fn test_class_name() {
+        assert_eq!(class_name("intro"), "xray");
+        assert_eq!(class_name("menus/options/audio"), "xray.menus.options");
+    }
+
+    #[test]
+    fn test_junit_report() {
+        let output = ::std::env::temp_dir().join(format!("xray-junit-test-{}", process::id()));
+        let write_record = |test_name: &str, status, summary, millis| {
+            let mut record = TestRecord::new(status, summary);
+            record.timings.total = Duration::from_millis(millis);
+            fs::create_dir_all(output.join(test_name)).unwrap();
+            fs::write(output.join(test_name).join(RESULT_FILE_NAME), record.to_json()).unwrap();
+        };
+        write_record("intro", TestStatus::Passed, "All good", 250);
+        write_record("menus/main", TestStatus::Failed, "Did not match.\n3 < 4", 500);
+        write_record("menus/options", TestStatus::Error, "Could not take screenshot.", 0);
+        write_png_creating_dirs(&output.join("menus/main/actual.png"), &rgbw()).unwrap();
+
+        write_junit_report(&output).unwrap();
+        let xml = fs::read_to_string(output.join(JUNIT_FILE_NAME)).unwrap();
+        assert!(xml.contains("<testsuites name=\"xray\" tests=\"3\" failures=\"1\" errors=\"1\" time=\"0.750\">"));
+        assert!(xml.contains("<testcase classname=\"xray\" name=\"intro\" time=\"0.250\"/>"));
+        assert!(xml.contains("<failure message=\"Did not match.\" type=\"failed\">Did not match.\n3 &lt; 4</failure>"));
+        assert!(xml.contains(&format!("[[ATTACHMENT|{}]]", output.join("menus/main/actual.png").display())));
+        assert!(!xml.contains("diff.png"));
+        assert!(xml.contains("<error message=\"Could not take screenshot.\" type=\"error\">"));
+
+        fs::remove_dir_all(&output).unwrap();
+    }
+}
@@ -7,8 +7,9 @@
 //! of the specified region, and compare it to a reference screenshot loaded from 
 //! `references/<test name>.png`.
 //! 
-//! To customise this behaviour, you should call `screenshot_test` (returns a `Result<(), XrayError>`)
-//! or `assert_screenshot_test` (panics on failure) with a custom `ScreenshotIo` and `ScreenshotCaptor`.
+//! To customise this behaviour, you should configure a `ScreenshotTest` with a custom `ScreenshotIo` and
+//! `ScreenshotCaptor`, and run it with `run` (returns a `Result<(), XrayError>`) or `assert` (panics on
+//! failure). `screenshot_test` and `assert_screenshot_test` are presets for the common cases.
 //! 
 //! 1. You may customise the method by which reference images are read, 
 //!    or output images are written by providing a custom `ScreenshotIo`. 
@@ -18,64 +19,326 @@
 //!      a custom implementation of `ScreenshotIo`.
 //! 2. You may customise the method by which screenshots are taken. This is done by providing a custom implementation
 //!    of `ScreenshotCaptor`
+//! 3. You may customise how screenshots are compared with reference images by passing an `ImageComparator` to
+//!    `ScreenshotTest::with_comparator`. `ExactComparator`, `ToleranceComparator`, `PerceptualComparator` and
+//!    `SsimComparator` are provided, or you may provide a custom implementation of `ImageComparator`.
 
 #[cfg(feature = "gl")]
 extern crate gl;
 extern crate image;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+extern crate serde_json;
+extern crate toml;
+#[cfg(feature = "macros")]
+extern crate xray_macros;
+
+mod builder;
+mod comparator;
+mod config;
+mod harness;
+#[cfg(feature = "headless")]
+mod headless;
+mod junit;
+mod mask;
+#[cfg(feature = "gl")]
+mod opengl;
+mod report;
+mod ssim;
 
 use std::borrow::ToOwned;
+use std::collections::HashSet;
+use std::error::Error;
 use std::fmt;
 use std::fs as fs;
 use std::fs::File;
-use std::os::raw::c_void;
 use std::path::{Path,PathBuf};
 use std::result::Result;
 
-use image::{GenericImage, ImageBuffer, ImageFormat, Rgba};
+use image::{GenericImage, ImageBuffer, ImageError, ImageFormat, Rgba};
 
 pub use image::DynamicImage;
+pub use harness::{gl_lock, test_name, FromTestName};
+#[cfg(feature = "headless")]
+pub use harness::with_headless_context;
+#[cfg(feature = "headless")]
+pub use headless::{ContextError, HeadlessContext};
+/// Turns a function into a screenshot test named after its module path and function name.
+///
+/// The test name is the function's module path with `::` replaced by `/`, followed by the function
+/// name, as returned by `test_name`. For example, `fn initial_map` in `tests/basic_rendering.rs` is
+/// named `basic_rendering/initial_map`, and compared against `references/basic_rendering/initial_map.png`.
+///
+/// The function may take any arguments which implement `FromTestName`, which are created for the
+/// test, such as an `FsScreenshotIo` for the test name and an `OpenGlScreenshotCaptor`:
+///
+/// ```ignore
+/// #[xray::test]
+/// fn initial_map(screenshot_io: FsScreenshotIo, captor: OpenGlScreenshotCaptor) {
+///     render_initial_map();
+///     xray::assert_screenshot_test(screenshot_io, captor, 0, 0, 1280, 720);
+/// }
+/// ```
+///
+/// The function becomes a normal `#[test]`, so other attributes such as `#[ignore]` or
+/// `#[should_panic]` may be added after it, and failures are reported by cargo's test runner.
+#[cfg(feature = "macros")]
+pub use xray_macros::test;
+pub use builder::{OutputPolicy, ScreenshotTest};
+pub use comparator::{
+    compare_images, ComparisonReport, DiffStats, ExactComparator, ImageComparator,
+    PerceptualComparator, Tolerance, ToleranceComparator, Verdict
+};
+pub use config::{
+    ComparatorConfig, ComparatorKind, Config, ConfigError, TestConfig, COMPARATOR_ENV_VAR, CONFIG_ENV_VAR,
+    CONFIG_FILE_NAME, OUTPUT_PATH_ENV_VAR, REFERENCES_PATH_ENV_VAR, VARIANTS_ENV_VAR
+};
+pub use junit::{write_junit_report, JUNIT_FILE_NAME};
+pub use mask::{MaskedComparator, Region};
+#[cfg(feature = "gl")]
+use opengl::renderer_variant_keys;
+#[cfg(feature = "gl")]
+pub use opengl::{CaptureBuffer, CaptureSource, OpenGlScreenshotCaptor, Orientation, ORIENTATION_ENV_VAR};
+pub use report::{
+    find_test_records, read_test_record, write_html_report, ImageSize, TestRecord, TestStatus, Timings, REPORT_FILE_NAME,
+    RESULT_FILE_NAME
+};
+pub use ssim::SsimComparator;
 
 /// Errors that occur while loading reference images
 /// or writing the output images.
+/// 
+/// The underlying error is kept, and returned by `source()`. Errors from the filesystem are
+/// wrapped in `ImageError::IoError`, and `source()` returns the `std::io::Error` itself.
+#[derive(Debug)]
 pub enum IoError {
-    OutputLocationUnavailable(String),
-    FailedWritingScreenshot(String, String),
-    FailedLoadingReferenceImage
+    /// The output directory which could not be created, and why.
+    OutputLocationUnavailable(String, std::io::Error),
+    /// The path or name of the image which could not be written, and why.
+    FailedWritingScreenshot(String, ImageError),
+    /// There is no reference image at this path or name yet, so the test is new.
+    ReferenceNotFound(String),
+    /// The reference image at this path or name exists but could not be decoded.
+    CorruptReferenceImage(String, ImageError),
+    /// The path or name of the reference image which could not be read, e.g. due to permissions, and why.
+    FailedLoadingReferenceImage(String, ImageError)
+}
+
+impl fmt::Display for IoError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            IoError::OutputLocationUnavailable(location, err) => write!(f, "Could not write to output location {}:\n{}", location, err),
+            IoError::FailedWritingScreenshot(name, err) => write!(f, "Could not write screenshot {}:\n{}", name, err),
+            IoError::ReferenceNotFound(name) => write!(f, "Reference image {} does not exist", name),
+            IoError::CorruptReferenceImage(name, err) => write!(f, "Reference image {} could not be decoded:\n{}", name, err),
+            IoError::FailedLoadingReferenceImage(name, err) => write!(f, "Reference image {} could not be read:\n{}", name, err)
+        }
+    }
+}
+
+impl Error for IoError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            IoError::OutputLocationUnavailable(_, err) => Some(err),
+            IoError::ReferenceNotFound(_) => None,
+            IoError::FailedWritingScreenshot(_, err) |
+            IoError::CorruptReferenceImage(_, err) |
+            IoError::FailedLoadingReferenceImage(_, err) => match err {
+                ImageError::IoError(io_error) => Some(io_error),
+                _ => Some(err)
+            }
+        }
+    }
 }
 
 /// Errors that occur with the screenshot comparison
 pub enum ScreenshotError {
     NoReferenceScreenshot(DynamicImage),
-    ScreenshotMismatch(DynamicImage, DynamicImage)
+    ScreenshotMismatch(DynamicImage, DynamicImage, Box<ComparisonReport>)
+}
+
+impl fmt::Display for ScreenshotError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ScreenshotError::NoReferenceScreenshot(_) => write!(f, "No reference screenshot found."),
+            ScreenshotError::ScreenshotMismatch(_, _, report) => write!(f, "Actual screenshot did not match expected screenshot.\n{}", report.summary)
+        }
+    }
+}
+
+impl fmt::Debug for ScreenshotError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ScreenshotError::NoReferenceScreenshot(actual) => f.debug_tuple("NoReferenceScreenshot")
+                .field(&actual.dimensions())
+                .finish(),
+            ScreenshotError::ScreenshotMismatch(actual, expected, report) => f.debug_tuple("ScreenshotMismatch")
+                .field(&actual.dimensions())
+                .field(&expected.dimensions())
+                .field(report)
+                .finish()
+        }
+    }
+}
+
+impl Error for ScreenshotError {}
+
+/// The framebuffer a screenshot was being read from when capturing it failed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FramebufferState {
+    /// The framebuffer object bound for reading, where 0 is the default framebuffer.
+    pub binding: u32,
+    /// The completeness status of that framebuffer, e.g. `GL_FRAMEBUFFER_COMPLETE`.
+    pub status: String
+}
+
+/// Details of a screenshot which could not be captured.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CaptureError {
+    /// What went wrong, e.g. the name of the GL error such as `GL_INVALID_OPERATION`.
+    pub reason: String,
+    /// The region which was requested, as `(x, y, width, height)`.
+    pub region: (i32, i32, u32, u32),
+    /// The framebuffer being read from, if the captor knows it.
+    pub framebuffer: Option<FramebufferState>
+}
+
+impl CaptureError {
+    pub fn new(reason: &str, x: i32, y: i32, width: u32, height: u32) -> CaptureError {
+        CaptureError {
+            reason: reason.to_string(),
+            region: (x, y, width, height),
+            framebuffer: None
+        }
+    }
+
+    pub fn with_framebuffer(self, binding: u32, status: &str) -> CaptureError {
+        CaptureError {
+            framebuffer: Some(FramebufferState { binding, status: status.to_string() }),
+            ..self
+        }
+    }
+}
+
+impl fmt::Display for CaptureError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (x, y, width, height) = self.region;
+        write!(f, "Could not take screenshot of {}x{} region at ({}, {}): {}", width, height, x, y, self.reason)?;
+        if let Some(framebuffer) = &self.framebuffer {
+            write!(f, "\nRead framebuffer {}: {}", framebuffer.binding, framebuffer.status)?;
+        }
+        Ok(())
+    }
 }
 
+impl Error for CaptureError {}
+
 /// Reasons that a test could fail.
+#[derive(Debug)]
 pub enum XrayError {
     Io(IoError),
-    CaptureError,
-    Screenshot(ScreenshotError)
+    CaptureError(CaptureError),
+    Screenshot(ScreenshotError),
+    /// `xray.toml` could not be loaded (see `Config::global`).
+    Config(ConfigError)
 }
 
 impl fmt::Display for XrayError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let text = match self {
-            XrayError::Io(io_error) => match io_error {
-                IoError::OutputLocationUnavailable(location) => format!("Could not write to output location: {}", location),
-                IoError::FailedWritingScreenshot(name, reason) => format!("Could not write screenshot {}:\n{}", name, reason),
-                IoError::FailedLoadingReferenceImage => format!("Reference image could not be loaded or parsed")
-            },
-            XrayError::CaptureError => "Could not take screenshot.".to_string(),
-            XrayError::Screenshot(screenshot_error) => match screenshot_error {
-                ScreenshotError::NoReferenceScreenshot(_) => "No reference screenshot found.".to_string(),
-                ScreenshotError::ScreenshotMismatch(_, _) => "Actual screenshot did not match expected screenshot.".to_string(),
-            }
-        };
-        write!(f, "{}", text)
+        match self {
+            XrayError::Io(io_error) => write!(f, "{}", io_error),
+            XrayError::CaptureError(capture_error) => write!(f, "{}", capture_error),
+            XrayError::Screenshot(screenshot_error) => write!(f, "{}", screenshot_error),
+            XrayError::Config(config_error) => write!(f, "{}", config_error)
+        }
+    }
+}
+
+impl Error for XrayError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            XrayError::Io(io_error) => io_error.source(),
+            XrayError::CaptureError(_) => None,
+            XrayError::Screenshot(screenshot_error) => screenshot_error.source(),
+            XrayError::Config(config_error) => config_error.source()
+        }
+    }
+}
+
+impl From<IoError> for XrayError {
+    fn from(io_error: IoError) -> XrayError {
+        XrayError::Io(io_error)
+    }
+}
+
+impl From<CaptureError> for XrayError {
+    fn from(capture_error: CaptureError) -> XrayError {
+        XrayError::CaptureError(capture_error)
+    }
+}
+
+impl From<ConfigError> for XrayError {
+    fn from(config_error: ConfigError) -> XrayError {
+        XrayError::Config(config_error)
+    }
+}
+
+impl From<ScreenshotError> for XrayError {
+    fn from(screenshot_error: ScreenshotError) -> XrayError {
+        XrayError::Screenshot(screenshot_error)
     }
 }
 
 type XrayResult<T> = Result<T, XrayError>;
 
+/// The directory in which `ScreenshotIo::default` looks for reference images, unless `xray.toml` sets another.
+pub const DEFAULT_REFERENCES_PATH: &str = "references";
+
+/// The directory to which `ScreenshotIo::default` writes the output of failed tests, unless `xray.toml` sets another.
+pub const DEFAULT_OUTPUT_PATH: &str = "test_output";
+
+/// The environment variable used to select an `UpdateMode` without changing code.
+pub const UPDATE_MODE_ENV_VAR: &str = "XRAY_UPDATE";
+
+/// Controls whether a screenshot test rewrites its reference image from the screenshot it captured,
+/// rather than failing.
+/// 
+/// Updated references are written using `ScreenshotIo::write_reference`, and the test passes. 
+/// Review the changes to your reference images (e.g. with `git diff`) before committing them.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UpdateMode {
+    /// Never write reference images. Tests without a reference image fail. This is the default.
+    #[default]
+    Off,
+    /// Write a reference image only for tests which do not have one yet. Existing references are
+    /// never overwritten, so tests which do not match their reference still fail.
+    New,
+    /// Write a reference image for any test which does not have one, or does not match it.
+    All
+}
+
+impl UpdateMode {
+    /// Reads the update mode from the `XRAY_UPDATE` environment variable. 
+    /// 
+    /// `1`, `true`, `yes` or `all` select `UpdateMode::All`, and `new` selects `UpdateMode::New`.
+    /// Any other value, or an unset variable, selects `UpdateMode::Off`.
+    pub fn from_env() -> UpdateMode {
+        std::env::var(UPDATE_MODE_ENV_VAR)
+            .map(|value| UpdateMode::parse(&value))
+            .unwrap_or(UpdateMode::Off)
+    }
+
+    fn parse(value: &str) -> UpdateMode {
+        match value.trim().to_lowercase().as_str() {
+            "1" | "true" | "yes" | "all" => UpdateMode::All,
+            "new" => UpdateMode::New,
+            _ => UpdateMode::Off
+        }
+    }
+}
+
 /// Load reference images for comparison and store image output in the event of a failed test. 
 /// 
 /// `xray` ships with one implementation
@@ -86,14 +349,82 @@ pub trait ScreenshotIo {
     fn prepare_output(&self) -> XrayResult<()>;
     /// Loads a reference image to compare to the screenshot taken by xray. The actual method
     /// used to load the image depends on your chosen implementation.
+    /// 
+    /// Returns `IoError::ReferenceNotFound` if there is no reference image yet. Only this error
+    /// fails the test with `ScreenshotError::NoReferenceScreenshot` (or writes a new reference in
+    /// `UpdateMode::New`); any other error fails the test with that error, so that an unreadable
+    /// reference image is never mistaken for a new test.
     fn load_reference(&self) -> XrayResult<DynamicImage>;
     /// Writes out the screenshot taken during the test in the event of a failed test.
-    fn write_actual(&self, &DynamicImage) -> XrayResult<()>;
+    fn write_actual(&self, actual: &DynamicImage) -> XrayResult<()>;
     /// Writes out the screenshot that was expected during the test in the event of a failed test.
-    fn write_expected(&self, &DynamicImage) -> XrayResult<()>;
+    fn write_expected(&self, expected: &DynamicImage) -> XrayResult<()>;
     /// Writes out an image containing only those pixels that were present in the newly captured image
     /// but not present in the reference image.
-    fn write_diff(&self, &DynamicImage) -> XrayResult<()>;
+    fn write_diff(&self, diff: &DynamicImage) -> XrayResult<()>;
+    /// Writes out an additional image produced by the comparator to help debug a failed test,
+    /// such as the similarity map produced by `SsimComparator`.
+    /// 
+    /// The default implementation discards the image.
+    fn write_debug_image(&self, _name: &str, _image: &DynamicImage) -> XrayResult<()> {
+        Ok(())
+    }
+    /// Loads an optional mask image marking the areas of the screenshot which should not be compared.
+    /// See `MaskedComparator` for how mask images are interpreted.
+    /// 
+    /// The default implementation never provides a mask.
+    fn load_mask(&self) -> XrayResult<Option<DynamicImage>> {
+        Ok(None)
+    }
+    /// Replaces the reference image with `reference`, when running with an `UpdateMode` other than `Off`.
+    /// 
+    /// The default implementation does not support updating references, and returns an error.
+    fn write_reference(&self, _reference: &DynamicImage) -> XrayResult<()> {
+        Err(XrayError::Io(IoError::FailedWritingScreenshot(
+            "reference".to_string(),
+            ImageError::UnsupportedError("This ScreenshotIo does not support updating reference images".to_string())
+        )))
+    }
+    /// Stores the captured screenshot as a pending reference image, to be reviewed and then either
+    /// accepted as the new reference or rejected. Called when a test fails or has no reference image.
+    /// 
+    /// The default implementation discards the image.
+    fn write_pending_reference(&self, _pending: &DynamicImage) -> XrayResult<()> {
+        Ok(())
+    }
+    /// Removes any pending reference image left behind by a previous failed run. Called when a test passes.
+    /// 
+    /// The default implementation does nothing.
+    fn clear_pending_reference(&self) -> XrayResult<()> {
+        Ok(())
+    }
+    /// Decides whether failing tests should update their reference image instead. 
+    /// 
+    /// The default implementation reads the mode from the `XRAY_UPDATE` environment variable
+    /// (see `UpdateMode::from_env`).
+    fn update_mode(&self) -> UpdateMode {
+        UpdateMode::from_env()
+    }
+    /// Records the outcome of the test once it has finished, whether it passed or failed.
+    /// Unlike the other output methods, this is also called for passing tests, so `prepare_output` may not have been called.
+    /// 
+    /// The default implementation discards the record.
+    fn write_result(&self, _record: &TestRecord) -> XrayResult<()> {
+        Ok(())
+    }
+    /// The key of the reference variant which `load_reference` loads, if it loads a variant rather than the
+    /// test's own reference image (see `FsScreenshotIo`). Recorded in `TestRecord::reference_variant`.
+    /// 
+    /// The default implementation returns `None`.
+    fn reference_variant(&self) -> Option<String> {
+        None
+    }
+    /// The name of the test, used to look up its settings in `xray.toml` (see `Config`).
+    /// 
+    /// The default implementation returns `None`, so only the suite-wide settings apply.
+    fn test_name(&self) -> Option<&str> {
+        None
+    }
 
     /// Returns a default implementation of `ScreenshotIo`. 
     /// 
@@ -105,8 +436,77 @@ pub trait ScreenshotIo {
     /// * `test_output/<test_name>/actual.png` containing the screenshot taken during the test.
     /// * `test_output/<test_name>/expected.png` containing a copy of the reference image which the screenshot was compared against.
     /// * `test_output/<test_name>/diff.png` containing those pixels of the newly taken screenshot that did not match the pixels in the reference image.
+    ///
+    /// The directories, update mode and reference variant keys can be changed for the whole test suite
+    /// in `xray.toml` (see `Config`). If it could not be loaded, the defaults are used, and `ScreenshotTest::run`
+    /// returns the error.
     fn default(test_name: &str) -> FsScreenshotIo {
-        FsScreenshotIo::new(test_name, "references", "test_output")
+        let default_config = Config::default();
+        let config = Config::global().unwrap_or(&default_config);
+        let screenshot_io = FsScreenshotIo::new(test_name, &config.references, &config.test_output)
+            .with_update_mode(config.update_mode());
+        match config.variants {
+            Some(ref variant_keys) => screenshot_io.with_variant_keys(variant_keys.clone()),
+            None => screenshot_io
+        }
+    }
+}
+
+impl<S: ScreenshotIo> ScreenshotIo for &S {
+    fn prepare_output(&self) -> XrayResult<()> {
+        (*self).prepare_output()
+    }
+
+    fn load_reference(&self) -> XrayResult<DynamicImage> {
+        (*self).load_reference()
+    }
+
+    fn reference_variant(&self) -> Option<String> {
+        (*self).reference_variant()
+    }
+
+    fn test_name(&self) -> Option<&str> {
+        (*self).test_name()
+    }
+
+    fn write_actual(&self, actual: &DynamicImage) -> XrayResult<()> {
+        (*self).write_actual(actual)
+    }
+
+    fn write_expected(&self, expected: &DynamicImage) -> XrayResult<()> {
+        (*self).write_expected(expected)
+    }
+
+    fn write_diff(&self, diff: &DynamicImage) -> XrayResult<()> {
+        (*self).write_diff(diff)
+    }
+
+    fn write_debug_image(&self, name: &str, image: &DynamicImage) -> XrayResult<()> {
+        (*self).write_debug_image(name, image)
+    }
+
+    fn load_mask(&self) -> XrayResult<Option<DynamicImage>> {
+        (*self).load_mask()
+    }
+
+    fn write_reference(&self, reference: &DynamicImage) -> XrayResult<()> {
+        (*self).write_reference(reference)
+    }
+
+    fn write_pending_reference(&self, pending: &DynamicImage) -> XrayResult<()> {
+        (*self).write_pending_reference(pending)
+    }
+
+    fn clear_pending_reference(&self) -> XrayResult<()> {
+        (*self).clear_pending_reference()
+    }
+
+    fn update_mode(&self) -> UpdateMode {
+        (*self).update_mode()
+    }
+
+    fn write_result(&self, record: &TestRecord) -> XrayResult<()> {
+        (*self).write_result(record)
     }
 }
 
@@ -118,6 +518,19 @@ pub trait ScreenshotIo {
 /// For example, for a references_path `tests/reference_images`, and a test_name `basics/menu`
 /// the library will look for a reference image in `tests/reference_images/basics/menu.png`.alloc
 /// 
+/// Tests which legitimately render differently on some platforms or GL renderers may have reference
+/// variants, `<references_path>/<test_name>@<key>.png`. The first variant found for the variant keys
+/// (see `with_variant_keys` and `detect_variant_keys`) is used instead of `<test_name>.png`, so with the
+/// keys `llvmpipe` and `linux`, `menus/main@llvmpipe.png` is compared if it exists, then `menus/main@linux.png`,
+/// then `menus/main.png`. The variant compared is recorded in `TestRecord::reference_variant`, and
+/// updated and pending references replace the variant which was compared.
+/// 
+/// The variant is chosen once, when the `FsScreenshotIo` is created or given its variant keys, so
+/// create it while the OpenGL context being tested is current.
+/// 
+/// If `<references_path>/<test_name>.mask.png` exists, it is used as a mask image marking areas
+/// of the screenshot to ignore (see `MaskedComparator`). Masks are shared by all variants.
+/// 
 /// It will store output images in <output_path>/<test_name> at the top level of your crate. As with 
 /// reference images, slashes may be used to use subdirectories. For example, given an output path
 /// `target/screenshots` and a test_name `basics/menu`, the following images will be written:
@@ -127,85 +540,298 @@ pub trait ScreenshotIo {
 /// * `target/screenshots/basics/menu/diff.png`
 /// 
 /// `actual.png` contains the screenshot taken for the test. 
+/// 
+/// Any debug images produced by the comparator are written alongside these as `<name>.png`,
+/// for example `target/screenshots/basics/menu/similarity.png` when using `SsimComparator`.
+/// 
+/// When updating references (see `UpdateMode`), the captured screenshot is written to 
+/// `<references_path>/<test_name>.png`. The update mode is read from the `XRAY_UPDATE` 
+/// environment variable, unless set with `with_update_mode`.
+/// 
+/// Otherwise, when a test fails or has no reference image, the captured screenshot is also written
+/// next to the reference as a pending reference, `<references_path>/<test_name>.new.png`. After reviewing it, 
+/// use `accept_pending_reference` to replace the reference with it, or `reject_pending_reference` to delete it.
+/// Pending references are removed when the test next passes. Use `find_pending_references` to list
+/// all pending references in a directory.
+/// 
+/// Every test, passing or failing, records its outcome (see `TestRecord`) in `<output_path>/<test_name>/result.json`.
+/// Once all tests have run, `xray report` (or `prune_test_records`, `write_html_report` and `write_junit_report`)
+/// builds an HTML report of them at `<output_path>/index.html` and a JUnit XML report at `<output_path>/junit.xml`.
 pub struct FsScreenshotIo {
     references_path: PathBuf,
     output_path: PathBuf,
-    test_name: String
+    test_name: String,
+    update_mode: Option<UpdateMode>,
+    reference_variant: Option<String>
 }
 
 /// Captures a region of the screen for comparison against a reference image.
 pub trait ScreenshotCaptor {
     /// Takes a screenshot of the area (x, y, x + width, y + height)
-    /// Returns a ScreenshotError::ErrorCapturingImage if the image could not be captured.
+    /// Returns an `XrayError::CaptureError` if the image could not be captured.
     fn capture_image(&self, x: i32, y: i32, width: u32, height: u32) -> XrayResult<DynamicImage>;
 }
 
-#[cfg(feature = "gl")]
-/// Captures a screenshot using `gl::ReadPixels`
-/// 
-/// To use this screenshot captor, OpenGL must be able to 
-/// load function pointers. If you use Piston or Glutin, this is likely already the case.
+impl FsScreenshotIo {
+    pub fn new<P: AsRef<Path>>(test_name: &str, references_path: P, output_path: P) -> FsScreenshotIo {
+        FsScreenshotIo {
+            references_path: references_path.as_ref().to_owned(),
+            output_path: output_path.as_ref().to_owned(),
+            test_name: test_name.to_string(),
+            update_mode: None,
+            reference_variant: None
+        }.with_variant_keys(detect_variant_keys())
+    }
+
+    /// Uses `update_mode` instead of reading it from the `XRAY_UPDATE` environment variable.
+    pub fn with_update_mode(self, update_mode: UpdateMode) -> FsScreenshotIo {
+        FsScreenshotIo { update_mode: Some(update_mode), ..self }
+    }
+
+    /// Looks for reference variants with `variant_keys`, most specific first, instead of the keys from
+    /// `detect_variant_keys`. With no keys, only `<test_name>.png` is used.
+    pub fn with_variant_keys(self, variant_keys: Vec<String>) -> FsScreenshotIo {
+        let reference_variant = variant_keys.into_iter().find(|key| {
+            self.references_path.join(format!("{}{}{}.png", &self.test_name, VARIANT_SEPARATOR, key)).exists()
+        });
+        FsScreenshotIo { reference_variant, ..self }
+    }
+
+    /// The file name of the reference image, without `.png`.
+    fn reference_stem(&self) -> String {
+        match self.reference_variant {
+            Some(ref key) => format!("{}{}{}", &self.test_name, VARIANT_SEPARATOR, key),
+            None => self.test_name.clone()
+        }
+    }
+
+    fn reference_path(&self) -> PathBuf {
+        self.references_path.join(format!("{}.png", self.reference_stem()))
+    }
+
+    fn pending_reference_path(&self) -> PathBuf {
+        self.references_path.join(format!("{}{}", self.reference_stem(), PENDING_REFERENCE_SUFFIX))
+    }
+
+    /// Whether this test has a pending reference image awaiting review.
+    pub fn has_pending_reference(&self) -> bool {
+        self.pending_reference_path().exists()
+    }
+
+    /// Replaces the reference image with the pending reference image. 
+    /// 
+    /// Returns `Ok(false)` if there was no pending reference image.
+    pub fn accept_pending_reference(&self) -> XrayResult<bool> {
+        let pending_path = self.pending_reference_path();
+        if !pending_path.exists() {
+            return Ok(false);
+        }
+        fs::rename(&pending_path, self.reference_path()).map_err(|err| failed_writing(&self.reference_path(), err))?;
+        Ok(true)
+    }
+
+    /// Deletes the pending reference image, keeping the current reference image. 
+    /// 
+    /// Returns `Ok(false)` if there was no pending reference image.
+    pub fn reject_pending_reference(&self) -> XrayResult<bool> {
+        let pending_path = self.pending_reference_path();
+        if !pending_path.exists() {
+            return Ok(false);
+        }
+        fs::remove_file(&pending_path).map_err(|err| failed_writing(&pending_path, err))?;
+        Ok(true)
+    }
+
+    fn write_image(&self, name: &str, img: &DynamicImage) -> XrayResult<()> {
+        write_png(&self.output_path.join(&self.test_name).join(name), img)
+    }
+}
+
+fn failed_writing<E: Into<ImageError>>(filename: &Path, err: E) -> XrayError {
+    XrayError::Io(IoError::FailedWritingScreenshot(filename.to_string_lossy().to_string(), err.into()))
+}
+
+fn failed_loading<E: Into<ImageError>>(filename: &Path, err: E) -> XrayError {
+    XrayError::Io(IoError::FailedLoadingReferenceImage(filename.to_string_lossy().to_string(), err.into()))
+}
+
+/// Classifies an error from loading a reference image as missing, corrupt or unreadable.
+fn reference_loading_error(filename: &Path, err: ImageError) -> XrayError {
+    let name = filename.to_string_lossy().to_string();
+    let io_error_kind = match err {
+        ImageError::IoError(ref io_error) => Some(io_error.kind()),
+        _ => None
+    };
+    match io_error_kind {
+        Some(std::io::ErrorKind::NotFound) => XrayError::Io(IoError::ReferenceNotFound(name)),
+        // Decoders report truncated or malformed data as IO errors of these kinds.
+        Some(std::io::ErrorKind::UnexpectedEof) |
+        Some(std::io::ErrorKind::InvalidData) |
+        None => XrayError::Io(IoError::CorruptReferenceImage(name, err)),
+        Some(_) => XrayError::Io(IoError::FailedLoadingReferenceImage(name, err))
+    }
+}
+
+fn write_png(filename: &Path, img: &DynamicImage) -> XrayResult<()> {
+    let mut file = File::create(filename).map_err(|err| failed_writing(filename, err))?;
+    img.write_to(&mut file, ImageFormat::PNG).map_err(|err| failed_writing(filename, err))
+}
+
+pub(crate) fn write_png_creating_dirs(filename: &Path, img: &DynamicImage) -> XrayResult<()> {
+    if let Some(parent) = filename.parent() {
+        fs::create_dir_all(parent).map_err(|err|
+            XrayError::Io(IoError::OutputLocationUnavailable(parent.to_string_lossy().to_string(), err))
+        )?;
+    }
+    write_png(filename, img)
+}
+
+/// The suffix added to a test name to give the filename of its pending reference image.
+pub const PENDING_REFERENCE_SUFFIX: &str = ".new.png";
+
+/// The suffix added to a test name to find its mask image, e.g. `references/<test_name>.mask.png`.
+pub const MASK_SUFFIX: &str = ".mask.png";
+
+/// Separates the test name from the variant key in the names of reference variants, e.g. `menus/main@llvmpipe.png`.
+pub const VARIANT_SEPARATOR: char = '@';
+
+/// Detects the reference variant keys (see `FsScreenshotIo`) for the platform running the tests, most specific first.
 /// 
-/// If you use a lower level library like `gl` directly, you may need to call
-/// `gl::load_with(|symbol| glfw.get_proc_address(s)))`
-/// or similar, depending on your choice of gl library and context library.
-pub struct OpenGlScreenshotCaptor {
+/// If a GL context is current, the first keys identify its renderer, such as `llvmpipe`, `nvidia`, `intel` or `amd`,
+/// based on `GL_RENDERER` and `GL_VENDOR`. The last key is the operating system, e.g. `linux`, `macos` or `windows`.
+pub fn detect_variant_keys() -> Vec<String> {
+    let mut variant_keys = renderer_variant_keys();
+    variant_keys.push(std::env::consts::OS.to_string());
+    variant_keys
 }
 
-#[cfg(feature = "gl")]
-impl ScreenshotCaptor for OpenGlScreenshotCaptor {
-    fn capture_image(&self, x: i32, y: i32, width: u32, height: u32) -> XrayResult<DynamicImage> {
-        let mut img = DynamicImage::new_rgba8(width, height);
-        unsafe {
-            let pixels = img.as_mut_rgba8().unwrap();
-            gl::PixelStorei(gl::PACK_ALIGNMENT, 1);
-            gl::PixelStorei(gl::UNPACK_ALIGNMENT, 1);
-            let height = height as i32;
-            let width = width as i32;
-            gl::ReadPixels(x, y, width, height, gl::RGBA, gl::UNSIGNED_BYTE, pixels.as_mut_ptr() as *mut c_void);
-            let error_code = gl::GetError();
-            if error_code != gl::NO_ERROR {
-                return Err(XrayError::CaptureError);
+/// Without OpenGL support, there is no renderer to detect.
+#[cfg(not(feature = "gl"))]
+fn renderer_variant_keys() -> Vec<String> {
+    Vec::new()
+}
+
+/// Lists the paths of all files under `root`, relative to `root` and using `/` separators.
+/// Returns an empty list if `root` does not exist.
+pub(crate) fn relative_file_paths(root: &Path) -> std::io::Result<Vec<String>> {
+    fn visit(dir: &Path, prefix: &str, found: &mut Vec<String>) -> std::io::Result<()> {
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().to_string();
+            if entry.file_type()?.is_dir() {
+                visit(&entry.path(), &format!("{}{}/", prefix, name), found)?;
+            } else {
+                found.push(format!("{}{}", prefix, name));
             }
         }
+        Ok(())
+    }
 
-        Ok(img)
+    let mut found = Vec::new();
+    if root.is_dir() {
+        visit(root, "", &mut found)?;
     }
+    found.sort();
+    Ok(found)
 }
 
-impl FsScreenshotIo {
-    fn new<P: AsRef<Path>>(test_name: &str, references_path: P, output_path: P) -> FsScreenshotIo {
-        FsScreenshotIo {
-            references_path: references_path.as_ref().to_owned(),
-            output_path: output_path.as_ref().to_owned(),
-            test_name: test_name.to_string()
+/// Lists the names of all tests with a pending reference image under `references_path`, 
+/// as written by `FsScreenshotIo`. Test names in subdirectories are returned with `/` separators.
+pub fn find_pending_references<P: AsRef<Path>>(references_path: P) -> std::io::Result<Vec<String>> {
+    Ok(relative_file_paths(references_path.as_ref())?.into_iter()
+        .filter(|path| path.ends_with(PENDING_REFERENCE_SUFFIX))
+        .map(|path| path[..path.len() - PENDING_REFERENCE_SUFFIX.len()].to_string())
+        .collect())
+}
+
+/// Lists the names of all tests with a reference image under `references_path`, as read by
+/// `FsScreenshotIo`. Pending references and mask images are not included.
+/// Test names in subdirectories are returned with `/` separators.
+pub fn find_references<P: AsRef<Path>>(references_path: P) -> std::io::Result<Vec<String>> {
+    const REFERENCE_SUFFIX: &str = ".png";
+    Ok(relative_file_paths(references_path.as_ref())?.into_iter()
+        .filter(|path| path.ends_with(REFERENCE_SUFFIX))
+        .filter(|path| !path.ends_with(PENDING_REFERENCE_SUFFIX) && !path.ends_with(MASK_SUFFIX))
+        .map(|path| path[..path.len() - REFERENCE_SUFFIX.len()].to_string())
+        .collect())
+}
+
+/// Lists the names of all tests which wrote failure output under `output_path`, as written by 
+/// `FsScreenshotIo`. A test is considered failed if `<output_path>/<test_name>/actual.png` exists and
+/// its `result.json` records a failure, so tests which passed after an earlier failure are not listed.
+/// Output without a `result.json`, written by earlier versions, is considered failed.
+/// Test names in subdirectories are returned with `/` separators.
+#[allow(clippy::unnecessary_map_or)] // Option::is_none_or needs Rust 1.82
+pub fn find_failed_tests<P: AsRef<Path>>(output_path: P) -> std::io::Result<Vec<String>> {
+    const ACTUAL_SUFFIX: &str = "/actual.png";
+    let output_path = output_path.as_ref();
+    Ok(relative_file_paths(output_path)?.into_iter()
+        .filter(|path| path.ends_with(ACTUAL_SUFFIX))
+        .map(|path| path[..path.len() - ACTUAL_SUFFIX.len()].to_string())
+        .filter(|test_name| read_test_record(output_path, test_name).map_or(true, |record| record.status.is_failure()))
+        .collect())
+}
+
+/// Removes the output of tests which no longer exist, for example because they were renamed or deleted,
+/// so that they are left out of reports, and returns their names.
+/// 
+/// A test is considered to no longer exist if its `result.json` records that it passed or updated its
+/// reference, but it has no reference image (or reference variant) under `references_path`. The output of
+/// failed tests is always kept, so that a failure is never hidden from the reports.
+pub fn prune_test_records<P: AsRef<Path>, Q: AsRef<Path>>(output_path: P, references_path: Q) -> std::io::Result<Vec<String>> {
+    let output_path = output_path.as_ref();
+    let references: HashSet<String> = find_references(references_path)?.into_iter()
+        .map(|name| {
+            let file_start = name.rfind('/').map_or(0, |index| index + 1);
+            match name[file_start..].find(VARIANT_SEPARATOR) {
+                Some(index) => name[..file_start + index].to_string(),
+                None => name
+            }
+        })
+        .collect();
+    let mut pruned = Vec::new();
+    for (test_name, record) in find_test_records(output_path)? {
+        if !record.status.is_failure() && !references.contains(&test_name) {
+            remove_test_output(output_path, &test_name)?;
+            pruned.push(test_name);
         }
     }
+    Ok(pruned)
+}
 
-    fn write_image(&self, name: &str, img: &DynamicImage) -> XrayResult<()> {
-        let filename = self.output_path.join(&self.test_name).join(name);
-        let mut file = File::create(&filename).or(Err(XrayError::Io(IoError::FailedWritingScreenshot(
-            filename.to_string_lossy().to_string(), 
-            "Could not open file for writing".to_string()
-        ))))?;
-        img.write_to(&mut file, ImageFormat::PNG).or_else(
-            |err| Err(XrayError::Io(IoError::FailedWritingScreenshot(
-                filename.to_string_lossy().to_string(), 
-                err.to_string())))
-        )
+/// Removes the files written under `<output_path>/<test_name>`, leaving the output of any tests in
+/// subdirectories.
+pub fn remove_test_output<P: AsRef<Path>>(output_path: P, test_name: &str) -> std::io::Result<()> {
+    let test_output = output_path.as_ref().join(test_name);
+    for entry in fs::read_dir(&test_output)? {
+        let entry = entry?;
+        if entry.file_type()?.is_file() {
+            fs::remove_file(entry.path())?;
+        }
     }
+    // Only succeeds once the directory is empty, which it won't be if it contains other tests.
+    let _ = fs::remove_dir(&test_output);
+    Ok(())
 }
 
 impl ScreenshotIo for FsScreenshotIo {
+    fn reference_variant(&self) -> Option<String> {
+        self.reference_variant.clone()
+    }
+
+    fn test_name(&self) -> Option<&str> {
+        Some(&self.test_name)
+    }
+
     fn prepare_output(&self) -> XrayResult<()> {
-        fs::create_dir_all(&self.output_path.join(&self.test_name)).or(
-            Err(XrayError::Io(IoError::OutputLocationUnavailable(self.output_path.to_string_lossy().to_string())))
+        fs::create_dir_all(self.output_path.join(&self.test_name)).map_err(|err|
+            XrayError::Io(IoError::OutputLocationUnavailable(self.output_path.to_string_lossy().to_string(), err))
         )
     }
 
     fn load_reference(&self) -> XrayResult<DynamicImage> {
-        let full_path = self.references_path.join(format!("{}.png", &self.test_name));
-        image::open(full_path).or(Err(XrayError::Io(IoError::FailedLoadingReferenceImage)))
+        let reference_path = self.reference_path();
+        image::open(&reference_path).map_err(|err| reference_loading_error(&reference_path, err))
     }
 
     fn write_actual(&self, actual: &DynamicImage) -> XrayResult<()> {
@@ -219,13 +845,40 @@ impl ScreenshotIo for FsScreenshotIo {
     fn write_diff(&self, actual: &DynamicImage) ->XrayResult<()> {
         self.write_image("diff.png", actual)
     }
-}
 
-fn compare_screenshot_images(reference_image: DynamicImage, actual_image: DynamicImage) -> XrayResult<()> {
-    if reference_image.raw_pixels() == actual_image.raw_pixels() { 
-        Ok(()) 
-    } else { 
-        Err(XrayError::Screenshot(ScreenshotError::ScreenshotMismatch(actual_image, reference_image)))
+    fn write_debug_image(&self, name: &str, image: &DynamicImage) -> XrayResult<()> {
+        self.write_image(&format!("{}.png", name), image)
+    }
+
+    fn load_mask(&self) -> XrayResult<Option<DynamicImage>> {
+        let full_path = self.references_path.join(format!("{}{}", &self.test_name, MASK_SUFFIX));
+        if !full_path.exists() {
+            return Ok(None);
+        }
+        image::open(&full_path).map(Some).map_err(|err| failed_loading(&full_path, err))
+    }
+
+    fn write_reference(&self, reference: &DynamicImage) -> XrayResult<()> {
+        write_png_creating_dirs(&self.reference_path(), reference)
+    }
+
+    fn write_pending_reference(&self, pending: &DynamicImage) -> XrayResult<()> {
+        write_png_creating_dirs(&self.pending_reference_path(), pending)
+    }
+
+    fn clear_pending_reference(&self) -> XrayResult<()> {
+        self.reject_pending_reference().and(Ok(()))
+    }
+
+    fn update_mode(&self) -> UpdateMode {
+        self.update_mode.unwrap_or_else(UpdateMode::from_env)
+    }
+
+    fn write_result(&self, record: &TestRecord) -> XrayResult<()> {
+        let result_path = self.output_path.join(&self.test_name).join(RESULT_FILE_NAME);
+        let record = TestRecord { test_name: self.test_name.clone(), ..record.clone() };
+        self.prepare_output()?;
+        fs::write(&result_path, record.to_json()).map_err(|err| failed_writing(&result_path, err))
     }
 }
 
@@ -262,41 +915,36 @@ pub fn diff_images(actual: &DynamicImage, expected: &DynamicImage) -> DynamicIma
     ))
 }
 
-fn handle_screenshot_error<S: ScreenshotIo>(screenshot_io: S, screenshot_error: XrayError) -> XrayResult<()> {
-    screenshot_io.prepare_output()?;
-    match screenshot_error {
-        XrayError::Screenshot(ScreenshotError::NoReferenceScreenshot(ref img)) => {
-            screenshot_io.write_actual(&img)?;
-        },
-        XrayError::Screenshot(ScreenshotError::ScreenshotMismatch(ref actual, ref expected)) => {
-            screenshot_io.write_expected(&expected)?;
-            screenshot_io.write_actual(&actual)?;
-            screenshot_io.write_diff(&diff_images(&actual, &expected))?;
-        },
-        _ => {}
-    }
-    Err(screenshot_error)
-}
-
 /// Tests the rendered image against the screenshot and returns a Ok(()) if they match, and a Err(ScreenshotError)
 /// should the comparison not match or encounter an error.
 /// 
 /// The reference image is loaded using `screenshot_io.load_reference()`, 
 /// while the test image is captured using `screenshot_captor.capture_image(x, y, width, height)`.
+/// 
+/// Every pixel must match exactly, unless `xray.toml` configures another comparator or ignore regions
+/// (see `Config`). To allow small differences, use `screenshot_test_with_tolerance`,
+/// or `screenshot_test_with_comparator` for other comparison policies.
 pub fn screenshot_test<S: ScreenshotIo, C: ScreenshotCaptor>(screenshot_io: S, screenshot_captor: C, x: i32, y: i32, width: u32, height: u32) -> XrayResult<()> {
-    screenshot_captor.capture_image(x, y, width, height)
-        .and_then(|captured_image| {
-            match screenshot_io.load_reference() {
-                Ok(reference_image) => Ok((reference_image, captured_image)),
-                Err(_) => Err(XrayError::Screenshot(ScreenshotError::NoReferenceScreenshot(captured_image)))
-            }
-        })
-        .and_then(|images| {
-            let (reference_image, captured_image) = images;
-            compare_screenshot_images(reference_image, captured_image)
-        })
-        .or_else(|err| handle_screenshot_error(screenshot_io, err))
-        .and(Ok(()))
+    ScreenshotTest::new(screenshot_io, screenshot_captor).with_region(x, y, width, height).run()
+}
+
+/// Tests the rendered image against the screenshot, allowing differences within the given `tolerance`.
+/// 
+/// Otherwise behaves like `screenshot_test`. If the differences exceed the tolerance, the returned
+/// `ScreenshotError::ScreenshotMismatch` contains `DiffStats` describing how far over the limit the screenshot was.
+pub fn screenshot_test_with_tolerance<S: ScreenshotIo, C: ScreenshotCaptor>(screenshot_io: S, screenshot_captor: C, tolerance: Tolerance, x: i32, y: i32, width: u32, height: u32) -> XrayResult<()> {
+    screenshot_test_with_comparator(screenshot_io, screenshot_captor, ToleranceComparator::new(tolerance), x, y, width, height)
+}
+
+/// Tests the rendered image against the screenshot, using `comparator` to decide whether they match.
+/// 
+/// Otherwise behaves like `screenshot_test`. If the comparator rejects the screenshot, the returned
+/// `ScreenshotError::ScreenshotMismatch` contains the comparator's `ComparisonReport`.
+pub fn screenshot_test_with_comparator<S: ScreenshotIo, C: ScreenshotCaptor, I: ImageComparator>(screenshot_io: S, screenshot_captor: C, comparator: I, x: i32, y: i32, width: u32, height: u32) -> XrayResult<()> {
+    ScreenshotTest::new(screenshot_io, screenshot_captor)
+        .with_comparator(comparator)
+        .with_region(x, y, width, height)
+        .run()
 }
 
 /// Tests the rendered image against a screenshot and panics if the images do
@@ -305,10 +953,22 @@ pub fn screenshot_test<S: ScreenshotIo, C: ScreenshotCaptor>(screenshot_io: S, s
 /// The reference image is loaded using `screenshot_io.load_reference()`, 
 /// while the test image is captured using `screenshot_captor.capture_image(x, y, width, height)`.
 pub fn assert_screenshot_test<S: ScreenshotIo, C: ScreenshotCaptor>(screenshot_io: S, screenshot_captor: C, x: i32, y: i32, width: u32, height: u32) {
-    let result = screenshot_test(screenshot_io, screenshot_captor, x, y, width, height);
-    if result.is_err() {
-        panic!(format!("{}", result.unwrap_err()))
-    }
+    ScreenshotTest::new(screenshot_io, screenshot_captor).with_region(x, y, width, height).assert()
+}
+
+/// Tests the rendered image against a screenshot and panics if the differences between
+/// the images exceed `tolerance` or the images are unable to be taken.
+pub fn assert_screenshot_test_with_tolerance<S: ScreenshotIo, C: ScreenshotCaptor>(screenshot_io: S, screenshot_captor: C, tolerance: Tolerance, x: i32, y: i32, width: u32, height: u32) {
+    assert_screenshot_test_with_comparator(screenshot_io, screenshot_captor, ToleranceComparator::new(tolerance), x, y, width, height);
+}
+
+/// Tests the rendered image against a screenshot and panics if `comparator` rejects
+/// the screenshot or the images are unable to be taken.
+pub fn assert_screenshot_test_with_comparator<S: ScreenshotIo, C: ScreenshotCaptor, I: ImageComparator>(screenshot_io: S, screenshot_captor: C, comparator: I, x: i32, y: i32, width: u32, height: u32) {
+    ScreenshotTest::new(screenshot_io, screenshot_captor)
+        .with_comparator(comparator)
+        .with_region(x, y, width, height)
+        .assert()
 }
 
 /// Takes a screenshot using OpenGL and panics if it does not match a reference image.
@@ -324,38 +984,85 @@ pub fn assert_screenshot_test<S: ScreenshotIo, C: ScreenshotCaptor>(screenshot_i
 /// * test_output/<test_name>/expected.png containing the reference image the screenshot was compared against.
 /// * test_output/<test_name>/diff.png containing the pixels from the screenshot that did not match the pixels in the reference image.
 /// 
-/// To customise any of this behaviour, create a custom `ScreenshotCaptor` and 
-/// `ScreenshotIo` and pass them to `screenshot_test` (returns a `Result<(), ScreenshotError>`) 
-/// or `assert_screenshot_test` (panics on fail)
+/// This is a preset for `ScreenshotTest::gl(test_name).with_region(x, y, width, height).assert()`.
+/// To customise any of this behaviour, configure a `ScreenshotTest` instead.
 #[cfg(feature = "gl")]
 pub fn gl_screenshot_test(test_name: &str, x: i32, y: i32, width: u32, height: u32) {
-    let fs_screenshot_io: FsScreenshotIo = FsScreenshotIo::default(test_name);
-    let screenshot_captor = OpenGlScreenshotCaptor {};
-    assert_screenshot_test(fs_screenshot_io, screenshot_captor, x, y, width, height);
+    ScreenshotTest::gl(test_name).with_region(x, y, width, height).assert()
+}
+
+/// Takes a screenshot using OpenGL and panics if it differs from a reference image
+/// by more than `tolerance` allows.
+/// 
+/// Otherwise behaves exactly like `gl_screenshot_test`.
+#[cfg(feature = "gl")]
+pub fn gl_screenshot_test_with_tolerance(test_name: &str, tolerance: Tolerance, x: i32, y: i32, width: u32, height: u32) {
+    gl_screenshot_test_with_comparator(test_name, ToleranceComparator::new(tolerance), x, y, width, height);
+}
+
+/// Takes a screenshot using OpenGL and panics if `comparator` decides it does not match
+/// the reference image.
+/// 
+/// Otherwise behaves exactly like `gl_screenshot_test`.
+#[cfg(feature = "gl")]
+pub fn gl_screenshot_test_with_comparator<I: ImageComparator>(test_name: &str, comparator: I, x: i32, y: i32, width: u32, height: u32) {
+    ScreenshotTest::gl(test_name)
+        .with_comparator(comparator)
+        .with_region(x, y, width, height)
+        .assert()
 }
 
 #[cfg(test)]
-mod tests {
+// test_success and test_fail build images they never use.
+#[allow(unused_variables)]
+pub mod tests {
 
     use super::*;
     use std::cell::RefCell;
+    // The glob import above includes the `#[xray::test]` attribute, which would shadow the built-in one.
+    #[cfg(feature = "macros")]
+    use std::prelude::v1::test;
 
-    struct FakeScreenshotIo {
-        reference_image: DynamicImage,
-        actual: RefCell<Option<DynamicImage>>,
-        expected: RefCell<Option<DynamicImage>>,
-        diff: RefCell<Option<DynamicImage>>
+    pub struct FakeScreenshotIo {
+        pub reference_image: Option<DynamicImage>,
+        pub actual: RefCell<Option<DynamicImage>>,
+        pub expected: RefCell<Option<DynamicImage>>,
+        pub diff: RefCell<Option<DynamicImage>>,
+        pub debug_images: RefCell<Vec<(String, DynamicImage)>>,
+        pub mask: Option<DynamicImage>,
+        pub update_mode: UpdateMode,
+        pub written_reference: RefCell<Option<DynamicImage>>,
+        pub pending_reference: RefCell<Option<DynamicImage>>,
+        pub result: RefCell<Option<TestRecord>>
     }
 
     impl FakeScreenshotIo {
-        fn new(reference_image: DynamicImage) -> FakeScreenshotIo {
+        pub fn new(reference_image: DynamicImage) -> FakeScreenshotIo {
             FakeScreenshotIo {
-                reference_image,
+                reference_image: Some(reference_image),
                 actual: RefCell::new(None),
                 expected: RefCell::new(None),
-                diff: RefCell::new(None)
+                diff: RefCell::new(None),
+                debug_images: RefCell::new(Vec::new()),
+                mask: None,
+                update_mode: UpdateMode::Off,
+                written_reference: RefCell::new(None),
+                pending_reference: RefCell::new(None),
+                result: RefCell::new(None)
             }
         }
+
+        pub fn without_reference() -> FakeScreenshotIo {
+            FakeScreenshotIo { reference_image: None, ..FakeScreenshotIo::new(rgbw()) }
+        }
+
+        pub fn with_update_mode(self, update_mode: UpdateMode) -> FakeScreenshotIo {
+            FakeScreenshotIo { update_mode, ..self }
+        }
+
+        pub fn with_mask(self, mask: DynamicImage) -> FakeScreenshotIo {
+            FakeScreenshotIo { mask: Some(mask), ..self }
+        }
     }
 
     impl ScreenshotIo for FakeScreenshotIo {
@@ -364,7 +1071,7 @@ mod tests {
         }
 
         fn load_reference(&self) -> XrayResult<DynamicImage> {
-            Ok(self.reference_image.clone())
+            self.reference_image.clone().ok_or_else(|| XrayError::Io(IoError::ReferenceNotFound("fake".to_string())))
         }
 
         fn write_actual(&self, image: &DynamicImage) -> XrayResult<()> {
@@ -381,18 +1088,84 @@ mod tests {
             self.diff.replace(Some(image.clone()));
             Ok(())
         }
+
+        fn write_debug_image(&self, name: &str, image: &DynamicImage) -> XrayResult<()> {
+            self.debug_images.borrow_mut().push((name.to_string(), image.clone()));
+            Ok(())
+        }
+
+        fn load_mask(&self) -> XrayResult<Option<DynamicImage>> {
+            Ok(self.mask.clone())
+        }
+
+        fn write_reference(&self, image: &DynamicImage) -> XrayResult<()> {
+            self.written_reference.replace(Some(image.clone()));
+            Ok(())
+        }
+
+        fn write_pending_reference(&self, image: &DynamicImage) -> XrayResult<()> {
+            self.pending_reference.replace(Some(image.clone()));
+            Ok(())
+        }
+
+        fn clear_pending_reference(&self) -> XrayResult<()> {
+            self.pending_reference.replace(None);
+            Ok(())
+        }
+
+        fn update_mode(&self) -> UpdateMode {
+            self.update_mode
+        }
+
+        fn write_result(&self, record: &TestRecord) -> XrayResult<()> {
+            self.result.replace(Some(record.clone()));
+            Ok(())
+        }
     }
 
-    struct FakeScreenshotCaptor {
-        screenshot: DynamicImage
+    pub struct FakeScreenshotCaptor {
+        pub screenshot: DynamicImage
     }
 
     impl ScreenshotCaptor for FakeScreenshotCaptor {
-        fn capture_image(&self, x: i32, y: i32, width: u32, height: u32) -> XrayResult<DynamicImage> {
-            return Ok(self.screenshot.clone());
+        fn capture_image(&self, _x: i32, _y: i32, _width: u32, _height: u32) -> XrayResult<DynamicImage> {
+            Ok(self.screenshot.clone())
         }
     }
 
+    pub fn rgbw() -> DynamicImage {
+        DynamicImage::ImageRgba8(ImageBuffer::from_vec(2, 2, 
+            vec![
+                255, 0, 0, 255,
+                0, 255, 0, 255,
+                0, 0, 255, 255,
+                255, 255, 255, 255
+            ]
+        ).unwrap())
+    }
+
+    pub fn rbgw() -> DynamicImage {
+        DynamicImage::ImageRgba8(ImageBuffer::from_vec(2, 2, 
+            vec![
+                255, 0, 0, 255,
+                0, 0, 255, 255,
+                0, 255, 0, 255,
+                255, 255, 255, 255
+            ]
+        ).unwrap())
+    }
+
+    pub fn rgbw_off_by(delta: u8) -> DynamicImage {
+        DynamicImage::ImageRgba8(ImageBuffer::from_vec(2, 2, 
+            vec![
+                255 - delta, 0, 0, 255,
+                0, 255, delta, 255,
+                0, 0, 255, 255,
+                255, 255, 255, 255
+            ]
+        ).unwrap())
+    }
+
     #[test]
     fn test_diff_images() {
         let rgbw = DynamicImage::ImageRgba8(ImageBuffer::from_vec(2, 2, 
@@ -484,4 +1257,312 @@ mod tests {
         let screenshot_captor = FakeScreenshotCaptor { screenshot: rbgw.clone() };
         assert_screenshot_test(screenshot_io, screenshot_captor, 0, 0, 2, 2);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_tolerance_success() {
+        let screenshot_io = FakeScreenshotIo::new(rgbw());
+        let screenshot_captor = FakeScreenshotCaptor { screenshot: rgbw_off_by(2) };
+        let tolerance = Tolerance::exact().with_max_channel_delta(2);
+        assert!(screenshot_test_with_tolerance(screenshot_io, screenshot_captor, tolerance, 0, 0, 2, 2).is_ok());
+    }
+
+    #[test]
+    fn test_tolerance_fail_reports_stats() {
+        let screenshot_io = FakeScreenshotIo::new(rgbw());
+        let screenshot_captor = FakeScreenshotCaptor { screenshot: rgbw_off_by(3) };
+        let tolerance = Tolerance::exact().with_max_channel_delta(2).with_max_differing_pixels(1);
+        match screenshot_test_with_tolerance(screenshot_io, screenshot_captor, tolerance, 0, 0, 2, 2) {
+            Err(XrayError::Screenshot(ScreenshotError::ScreenshotMismatch(_, _, report))) => {
+                assert_eq!(report.comparator, "tolerance");
+                assert_eq!(report.stats.differing_pixels, 2);
+                assert_eq!(report.stats.excess_pixels(), 1);
+                assert_eq!(report.stats.max_channel_delta, 3);
+            },
+            _ => panic!("Expected a screenshot mismatch")
+        }
+    }
+
+    #[test]
+    fn test_custom_comparator() {
+        struct AlwaysMatches;
+
+        impl ImageComparator for AlwaysMatches {
+            fn compare(&self, actual: &DynamicImage, expected: &DynamicImage) -> Verdict {
+                Verdict::Match(ComparisonReport::new("always", compare_images(actual, expected, &Tolerance::exact()), String::new()))
+            }
+        }
+
+        let screenshot_io = FakeScreenshotIo::new(rgbw());
+        let screenshot_captor = FakeScreenshotCaptor { screenshot: rbgw() };
+        assert!(screenshot_test_with_comparator(screenshot_io, screenshot_captor, AlwaysMatches, 0, 0, 2, 2).is_ok());
+    }
+
+    #[test]
+    fn test_debug_images_written_on_failure() {
+        let screenshot_io = FakeScreenshotIo::new(rgbw());
+        let screenshot_captor = FakeScreenshotCaptor { screenshot: rbgw() };
+        assert!(screenshot_test_with_comparator(&screenshot_io, screenshot_captor, SsimComparator::default(), 0, 0, 2, 2).is_err());
+        let debug_images = screenshot_io.debug_images.borrow();
+        assert_eq!(debug_images.len(), 1);
+        assert_eq!(debug_images[0].0, "similarity");
+    }
+
+    #[test]
+    fn test_mask_from_screenshot_io() {
+        let mask = DynamicImage::ImageRgba8(ImageBuffer::from_vec(2, 2, vec![
+            0, 0, 0, 0,
+            255, 255, 255, 255,
+            255, 255, 255, 255,
+            0, 0, 0, 0
+        ]).unwrap());
+        let screenshot_io = FakeScreenshotIo::new(rgbw()).with_mask(mask);
+        let screenshot_captor = FakeScreenshotCaptor { screenshot: rbgw() };
+        assert_screenshot_test(screenshot_io, screenshot_captor, 0, 0, 2, 2);
+    }
+
+    #[test]
+    fn test_update_mode_parse() {
+        assert_eq!(UpdateMode::parse("1"), UpdateMode::All);
+        assert_eq!(UpdateMode::parse("ALL"), UpdateMode::All);
+        assert_eq!(UpdateMode::parse("new"), UpdateMode::New);
+        assert_eq!(UpdateMode::parse("0"), UpdateMode::Off);
+        assert_eq!(UpdateMode::parse(""), UpdateMode::Off);
+    }
+
+    #[test]
+    fn test_missing_reference_fails_without_update() {
+        let screenshot_io = FakeScreenshotIo::without_reference();
+        let screenshot_captor = FakeScreenshotCaptor { screenshot: rbgw() };
+        assert!(screenshot_test(&screenshot_io, screenshot_captor, 0, 0, 2, 2).is_err());
+        assert!(screenshot_io.written_reference.borrow().is_none());
+        assert!(screenshot_io.actual.borrow().is_some());
+    }
+
+    #[test]
+    fn test_update_new_writes_missing_reference() {
+        let screenshot_io = FakeScreenshotIo::without_reference().with_update_mode(UpdateMode::New);
+        let screenshot_captor = FakeScreenshotCaptor { screenshot: rbgw() };
+        assert!(screenshot_test(&screenshot_io, screenshot_captor, 0, 0, 2, 2).is_ok());
+        assert_eq!(screenshot_io.written_reference.borrow().as_ref().unwrap().raw_pixels(), rbgw().raw_pixels());
+    }
+
+    #[test]
+    fn test_update_new_keeps_existing_reference() {
+        let screenshot_io = FakeScreenshotIo::new(rgbw()).with_update_mode(UpdateMode::New);
+        let screenshot_captor = FakeScreenshotCaptor { screenshot: rbgw() };
+        assert!(screenshot_test(&screenshot_io, screenshot_captor, 0, 0, 2, 2).is_err());
+        assert!(screenshot_io.written_reference.borrow().is_none());
+    }
+
+    #[test]
+    fn test_update_all_overwrites_mismatched_reference() {
+        let screenshot_io = FakeScreenshotIo::new(rgbw()).with_update_mode(UpdateMode::All);
+        let screenshot_captor = FakeScreenshotCaptor { screenshot: rbgw() };
+        assert!(screenshot_test(&screenshot_io, screenshot_captor, 0, 0, 2, 2).is_ok());
+        assert_eq!(screenshot_io.written_reference.borrow().as_ref().unwrap().raw_pixels(), rbgw().raw_pixels());
+        assert!(screenshot_io.actual.borrow().is_none());
+    }
+
+    #[test]
+    fn test_pending_reference_written_on_failure_and_cleared_on_success() {
+        let screenshot_io = FakeScreenshotIo::new(rgbw());
+        assert!(screenshot_test(&screenshot_io, FakeScreenshotCaptor { screenshot: rbgw() }, 0, 0, 2, 2).is_err());
+        assert_eq!(screenshot_io.pending_reference.borrow().as_ref().unwrap().raw_pixels(), rbgw().raw_pixels());
+
+        assert!(screenshot_test(&screenshot_io, FakeScreenshotCaptor { screenshot: rgbw() }, 0, 0, 2, 2).is_ok());
+        assert!(screenshot_io.pending_reference.borrow().is_none());
+    }
+
+    #[test]
+    fn test_result_recorded() {
+        let status = |screenshot_io: &FakeScreenshotIo| screenshot_io.result.borrow().as_ref().map(|record| record.status);
+
+        let screenshot_io = FakeScreenshotIo::new(rgbw());
+        assert!(screenshot_test(&screenshot_io, FakeScreenshotCaptor { screenshot: rgbw() }, 0, 0, 2, 2).is_ok());
+        assert_eq!(status(&screenshot_io), Some(TestStatus::Passed));
+        assert!(screenshot_test(&screenshot_io, FakeScreenshotCaptor { screenshot: rbgw() }, 0, 0, 2, 2).is_err());
+        assert_eq!(status(&screenshot_io), Some(TestStatus::Failed));
+        {
+            let result = screenshot_io.result.borrow();
+            let record = result.as_ref().unwrap();
+            assert_eq!(record.comparator.as_deref(), Some("exact"));
+            assert_eq!(record.stats.as_ref().map(|stats| stats.differing_pixels), Some(2));
+            assert_eq!(record.changed_regions, vec![Region::new(0, 0, 2, 2)]);
+            assert_eq!(record.actual_size, Some(ImageSize { width: 2, height: 2 }));
+        }
+
+        let screenshot_io = FakeScreenshotIo::without_reference();
+        assert!(screenshot_test(&screenshot_io, FakeScreenshotCaptor { screenshot: rgbw() }, 0, 0, 2, 2).is_err());
+        assert_eq!(status(&screenshot_io), Some(TestStatus::NoReference));
+
+        let screenshot_io = FakeScreenshotIo::without_reference().with_update_mode(UpdateMode::New);
+        assert!(screenshot_test(&screenshot_io, FakeScreenshotCaptor { screenshot: rgbw() }, 0, 0, 2, 2).is_ok());
+        assert_eq!(status(&screenshot_io), Some(TestStatus::Updated));
+    }
+
+    #[test]
+    fn test_error_source_chain() {
+        let root = std::env::temp_dir().join(format!("xray-error-test-{}", std::process::id()));
+        // A directory in place of the reference image can be opened, but not read.
+        fs::create_dir_all(root.join("unreadable.png")).unwrap();
+        let screenshot_io = FsScreenshotIo::new("unreadable", &root, &root);
+        let err = screenshot_io.load_reference().err().unwrap();
+        assert!(format!("{:?}", err).starts_with("Io(FailedLoadingReferenceImage("));
+        assert!(err.source().unwrap().downcast_ref::<std::io::Error>().is_some());
+
+        // A file in place of the output directory can't have the test's directory created in it.
+        fs::write(root.join("output"), b"").unwrap();
+        let unwritable = FsScreenshotIo::new("unwritable", root.clone(), root.join("output"));
+        let output_err = unwritable.prepare_output().unwrap_err();
+        assert!(format!("{:?}", output_err).starts_with("Io(OutputLocationUnavailable("));
+        assert!(output_err.source().unwrap().downcast_ref::<std::io::Error>().is_some());
+        fs::remove_dir_all(&root).unwrap();
+
+        let boxed: Box<dyn Error> = Box::new(err);
+        assert!(boxed.to_string().starts_with("Reference image "));
+
+        let mismatch = screenshot_test(FakeScreenshotIo::new(rgbw()), FakeScreenshotCaptor { screenshot: rbgw() }, 0, 0, 2, 2).unwrap_err();
+        assert!(format!("{:?}", mismatch).starts_with("Screenshot(ScreenshotMismatch((2, 2), (2, 2), "));
+        assert!(mismatch.source().is_none());
+    }
+
+    #[test]
+    fn test_missing_and_corrupt_references() {
+        let root = std::env::temp_dir().join(format!("xray-corrupt-test-{}", std::process::id()));
+        let references = root.join("references");
+        let output = root.join("test_output");
+        let missing = FsScreenshotIo::new("missing", &references, &output).with_update_mode(UpdateMode::Off);
+        match missing.load_reference() {
+            Err(XrayError::Io(IoError::ReferenceNotFound(_))) => {},
+            other => panic!("Expected a missing reference, got {:?}", other.err())
+        }
+
+        fs::create_dir_all(&references).unwrap();
+        fs::write(references.join("corrupt.png"), b"not a png").unwrap();
+        let corrupt = FsScreenshotIo::new("corrupt", &references, &output).with_update_mode(UpdateMode::New);
+        match screenshot_test(&corrupt, FakeScreenshotCaptor { screenshot: rgbw() }, 0, 0, 2, 2) {
+            Err(XrayError::Io(IoError::CorruptReferenceImage(..))) => {},
+            other => panic!("Expected a corrupt reference, got {:?}", other.err())
+        }
+        assert_eq!(fs::read(references.join("corrupt.png")).unwrap(), b"not a png");
+        assert!(!corrupt.has_pending_reference());
+        let records = find_test_records(&output).unwrap();
+        assert_eq!(records[0].1.status, TestStatus::Error);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_fs_pending_reference_accept_and_reject() {
+        let root = std::env::temp_dir().join(format!("xray-pending-test-{}", std::process::id()));
+        let references = root.join("references");
+        let output = root.join("test_output");
+        let screenshot_io = FsScreenshotIo::new("menus/main", &references, &output).with_update_mode(UpdateMode::Off);
+
+        assert!(screenshot_test(&screenshot_io, FakeScreenshotCaptor { screenshot: rbgw() }, 0, 0, 2, 2).is_err());
+        assert!(screenshot_io.has_pending_reference());
+        assert_eq!(find_pending_references(&references).unwrap(), vec!["menus/main".to_string()]);
+
+        assert!(screenshot_io.accept_pending_reference().unwrap());
+        assert!(!screenshot_io.has_pending_reference());
+        assert_eq!(find_references(&references).unwrap(), vec!["menus/main".to_string()]);
+        assert!(screenshot_test(&screenshot_io, FakeScreenshotCaptor { screenshot: rbgw() }, 0, 0, 2, 2).is_ok());
+
+        assert!(screenshot_test(&screenshot_io, FakeScreenshotCaptor { screenshot: rgbw() }, 0, 0, 2, 2).is_err());
+        assert!(screenshot_io.reject_pending_reference().unwrap());
+        assert!(!screenshot_io.reject_pending_reference().unwrap());
+        assert!(find_pending_references(&references).unwrap().is_empty());
+        assert!(screenshot_test(&screenshot_io, FakeScreenshotCaptor { screenshot: rbgw() }, 0, 0, 2, 2).is_ok());
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_fs_reference_variants() {
+        let root = std::env::temp_dir().join(format!("xray-variant-test-{}", std::process::id()));
+        let references = root.join("references");
+        let output = root.join("test_output");
+        let variant_keys = vec!["llvmpipe".to_string(), "linux".to_string()];
+        let screenshot_io = || FsScreenshotIo::new("menus/main", &references, &output)
+            .with_update_mode(UpdateMode::Off)
+            .with_variant_keys(variant_keys.clone());
+        write_png_creating_dirs(&references.join("menus/main.png"), &rgbw()).unwrap();
+        write_png_creating_dirs(&references.join("menus/main@linux.png"), &rbgw()).unwrap();
+        write_png_creating_dirs(&references.join("menus/main@nvidia.png"), &rgbw_off_by(1)).unwrap();
+
+        let linux_io = screenshot_io();
+        assert_eq!(linux_io.reference_variant(), Some("linux".to_string()));
+        assert!(screenshot_test(&linux_io, FakeScreenshotCaptor { screenshot: rbgw() }, 0, 0, 2, 2).is_ok());
+
+        // The variant is chosen when the FsScreenshotIo is created.
+        write_png_creating_dirs(&references.join("menus/main@llvmpipe.png"), &rgbw_off_by(2)).unwrap();
+        assert_eq!(linux_io.reference_variant(), Some("linux".to_string()));
+        let screenshot_io = screenshot_io();
+        assert!(screenshot_test(&screenshot_io, FakeScreenshotCaptor { screenshot: rbgw() }, 0, 0, 2, 2).is_err());
+        let result = fs::read_to_string(output.join("menus/main").join(RESULT_FILE_NAME)).unwrap();
+        assert_eq!(TestRecord::from_json(&result).unwrap().reference_variant, Some("llvmpipe".to_string()));
+        assert_eq!(find_pending_references(&references).unwrap(), vec!["menus/main@llvmpipe".to_string()]);
+        assert!(screenshot_io.accept_pending_reference().unwrap());
+        assert_eq!(image::open(references.join("menus/main@llvmpipe.png")).unwrap().raw_pixels(), rbgw().raw_pixels());
+
+        let plain = FsScreenshotIo::new("menus/main", &references, &output).with_variant_keys(Vec::new());
+        assert_eq!(plain.reference_variant(), None);
+        assert_eq!(plain.load_reference().unwrap().raw_pixels(), rgbw().raw_pixels());
+        assert!(detect_variant_keys().ends_with(&[std::env::consts::OS.to_string()]));
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_prune_test_records() {
+        let root = std::env::temp_dir().join(format!("xray-prune-test-{}", std::process::id()));
+        let references = root.join("references");
+        let output = root.join("test_output");
+        for test_name in &["menus/main", "menus/main/options", "intro", "credits"] {
+            let screenshot_io = FsScreenshotIo::new(test_name, &references, &output)
+                .with_update_mode(UpdateMode::New)
+                .with_variant_keys(Vec::new());
+            assert!(screenshot_test(&screenshot_io, FakeScreenshotCaptor { screenshot: rgbw() }, 0, 0, 2, 2).is_ok());
+        }
+        let failing = FsScreenshotIo::new("outro", &references, &output).with_update_mode(UpdateMode::Off);
+        assert!(screenshot_test(&failing, FakeScreenshotCaptor { screenshot: rgbw() }, 0, 0, 2, 2).is_err());
+        fs::rename(references.join("intro.png"), references.join("intro@linux.png")).unwrap();
+        fs::remove_file(references.join("menus/main.png")).unwrap();
+        fs::remove_file(references.join("credits.png")).unwrap();
+        fs::remove_file(references.join("outro.new.png")).unwrap();
+
+        assert_eq!(prune_test_records(&output, &references).unwrap(), vec!["credits".to_string(), "menus/main".to_string()]);
+        let remaining: Vec<_> = find_test_records(&output).unwrap().into_iter().map(|(test_name, _)| test_name).collect();
+        assert_eq!(remaining, vec!["intro", "menus/main/options", "outro"]);
+        assert!(!output.join("credits").exists());
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_find_failed_tests() {
+        let root = std::env::temp_dir().join(format!("xray-failed-test-{}", std::process::id()));
+        let references = root.join("references");
+        let output = root.join("test_output");
+        let failing = FsScreenshotIo::new("menus/options", &references, &output).with_update_mode(UpdateMode::Off);
+        assert!(screenshot_test(failing, FakeScreenshotCaptor { screenshot: rbgw() }, 0, 0, 2, 2).is_err());
+        let failing = FsScreenshotIo::new("intro", &references, &output).with_update_mode(UpdateMode::Off);
+        assert!(screenshot_test(failing, FakeScreenshotCaptor { screenshot: rbgw() }, 0, 0, 2, 2).is_err());
+
+        assert_eq!(find_failed_tests(&output).unwrap(), vec!["intro".to_string(), "menus/options".to_string()]);
+        assert!(find_failed_tests(root.join("missing")).unwrap().is_empty());
+
+        let passing = FsScreenshotIo::new("intro", &references, &output).with_update_mode(UpdateMode::New);
+        assert!(screenshot_test(&passing, FakeScreenshotCaptor { screenshot: rbgw() }, 0, 0, 2, 2).is_ok());
+        assert!(screenshot_test(&passing, FakeScreenshotCaptor { screenshot: rbgw() }, 0, 0, 2, 2).is_ok());
+        assert!(output.join("intro").join("actual.png").exists());
+        assert_eq!(find_failed_tests(&output).unwrap(), vec!["menus/options".to_string()]);
+        assert_eq!(find_test_records(&output).unwrap().len(), 2);
+        assert!(!output.join(REPORT_FILE_NAME).exists());
+        assert!(!output.join(JUNIT_FILE_NAME).exists());
+        let result = fs::read_to_string(output.join("intro").join(RESULT_FILE_NAME)).unwrap();
+        assert!(result.contains("\"test_name\": \"intro\""));
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
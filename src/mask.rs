@@ -0,0 +1,247 @@
+//! Excluding parts of a screenshot from comparison, such as FPS counters or clocks which
+//! change on every run.
+
+use image::{DynamicImage, GenericImage, Rgba, RgbaImage};
+
+use comparator::{channel_delta, for_each_pixel_pair, ImageComparator, Verdict};
+use diff_images;
+
+/// A rectangular area of a screenshot, with its origin in the top left.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Region {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32
+}
+
+impl Region {
+    pub fn new(x: u32, y: u32, width: u32, height: u32) -> Region {
+        Region { x, y, width, height }
+    }
+
+    /// Whether the pixel at (x, y) lies within this region.
+    pub fn contains(&self, x: u32, y: u32) -> bool {
+        x >= self.x && y >= self.y && x - self.x < self.width && y - self.y < self.height
+    }
+}
+
+/// Finds the bounding boxes of the areas which changed between two images, ignoring pixels for
+/// which `is_ignored` returns true.
+///
+/// A pixel has changed if any of its channels differ at all, or it is missing from one of the images.
+/// Changed pixels which touch, including diagonally, are grouped into the same area.
+/// Areas are returned in the order of their top left-most pixel, scanning rows from the top.
+pub(crate) fn find_changed_regions<F: Fn(u32, u32) -> bool>(actual: &DynamicImage, expected: &DynamicImage, is_ignored: F) -> Vec<Region> {
+    let width = actual.width().max(expected.width());
+    let height = actual.height().max(expected.height());
+    let index = |x: u32, y: u32| (y * width + x) as usize;
+    let mut changed = vec![false; width as usize * height as usize];
+    for_each_pixel_pair(&actual.to_rgba(), &expected.to_rgba(), |x, y, pixels| {
+        #[allow(clippy::unnecessary_map_or)] // Option::is_none_or needs Rust 1.82
+        let differs = pixels.map_or(true, |(a, e)| channel_delta(a, e) > 0);
+        changed[index(x, y)] = differs && !is_ignored(x, y);
+    });
+
+    let mut regions = Vec::new();
+    let mut stack = Vec::new();
+    for start_y in 0..height {
+        for start_x in 0..width {
+            if !changed[index(start_x, start_y)] {
+                continue;
+            }
+            changed[index(start_x, start_y)] = false;
+            stack.push((start_x, start_y));
+            let (mut min_x, mut min_y, mut max_x, mut max_y) = (start_x, start_y, start_x, start_y);
+            while let Some((x, y)) = stack.pop() {
+                min_x = min_x.min(x);
+                min_y = min_y.min(y);
+                max_x = max_x.max(x);
+                max_y = max_y.max(y);
+                for ny in y.saturating_sub(1)..(y + 2).min(height) {
+                    for nx in x.saturating_sub(1)..(x + 2).min(width) {
+                        if changed[index(nx, ny)] {
+                            changed[index(nx, ny)] = false;
+                            stack.push((nx, ny));
+                        }
+                    }
+                }
+            }
+            regions.push(Region::new(min_x, min_y, max_x - min_x + 1, max_y - min_y + 1));
+        }
+    }
+    regions
+}
+
+const HATCH_COLOUR: Rgba<u8> = Rgba { data: [128, 128, 128, 255] };
+const HATCH_SPACING: u32 = 6;
+
+/// Whether a pixel of a mask image marks its position as ignored. Black or fully transparent
+/// pixels are compared as normal; any other pixel is ignored.
+fn is_masked_pixel(pixel: Rgba<u8>) -> bool {
+    let [r, g, b, a] = pixel.data;
+    a > 0 && (r > 0 || g > 0 || b > 0)
+}
+
+/// Wraps another comparator, skipping pixels within ignored regions or marked in a mask image.
+///
+/// Masked pixels are treated as matching the reference image, whatever the screenshot contains.
+/// Any masked pixels which did differ are counted in `DiffStats::ignored_pixels`, and masked
+/// areas are drawn with grey hatching in the diff image.
+///
+/// A mask image is aligned with the top left of the screenshot. Pixels which are black or fully
+/// transparent in the mask are compared as normal, while any other pixel (e.g. white) is ignored.
+/// `FsScreenshotIo` loads a mask image automatically from `<references_path>/<test_name>.mask.png`
+/// if one exists.
+pub struct MaskedComparator<I: ImageComparator> {
+    pub comparator: I,
+    pub regions: Vec<Region>,
+    pub mask_image: Option<RgbaImage>
+}
+
+impl<I: ImageComparator> MaskedComparator<I> {
+    pub fn new(comparator: I) -> MaskedComparator<I> {
+        MaskedComparator {
+            comparator,
+            regions: Vec::new(),
+            mask_image: None
+        }
+    }
+
+    /// Ignores the given rectangular region of the screenshot.
+    pub fn with_region(mut self, region: Region) -> MaskedComparator<I> {
+        self.regions.push(region);
+        self
+    }
+
+    /// Ignores the pixels marked in `mask`, as described in the documentation of `MaskedComparator`.
+    pub fn with_mask_image(self, mask: &DynamicImage) -> MaskedComparator<I> {
+        MaskedComparator { mask_image: Some(mask.to_rgba()), ..self }
+    }
+
+    /// Whether the pixel at (x, y) should be skipped during comparison.
+    pub fn is_masked(&self, x: u32, y: u32) -> bool {
+        self.regions.iter().any(|region| region.contains(x, y))
+            || self.mask_image.as_ref().is_some_and(|mask| {
+                mask.in_bounds(x, y) && is_masked_pixel(*mask.get_pixel(x, y))
+            })
+    }
+}
+
+impl<I: ImageComparator> ImageComparator for MaskedComparator<I> {
+    fn compare(&self, actual: &DynamicImage, expected: &DynamicImage) -> Verdict {
+        let expected_rgba = expected.to_rgba();
+        let mut masked_actual = actual.to_rgba();
+        let mut masked_differing_pixels = 0;
+        for y in 0..masked_actual.height() {
+            for x in 0..masked_actual.width() {
+                if self.is_masked(x, y) && expected_rgba.in_bounds(x, y) {
+                    let expected_pixel = *expected_rgba.get_pixel(x, y);
+                    if *masked_actual.get_pixel(x, y) != expected_pixel {
+                        masked_differing_pixels += 1;
+                        masked_actual.put_pixel(x, y, expected_pixel);
+                    }
+                }
+            }
+        }
+        let masked_actual = DynamicImage::ImageRgba8(masked_actual);
+
+        let verdict = self.comparator.compare(&masked_actual, expected);
+        let is_match = verdict.is_match();
+        let mut report = verdict.into_report();
+        report.stats.ignored_pixels += masked_differing_pixels;
+        report.summary = format!("{} {} differing pixels were masked.", report.summary, masked_differing_pixels);
+
+        let mut diff = report.diff_image.take()
+            .unwrap_or_else(|| diff_images(&masked_actual, expected))
+            .to_rgba();
+        for y in 0..diff.height() {
+            for x in 0..diff.width() {
+                if self.is_masked(x, y) {
+                    let on_hatch_line = (x + y) % HATCH_SPACING == 0;
+                    diff.put_pixel(x, y, if on_hatch_line { HATCH_COLOUR } else { Rgba { data: [0, 0, 0, 0] } });
+                }
+            }
+        }
+        report.diff_image = Some(DynamicImage::ImageRgba8(diff));
+
+        if is_match {
+            Verdict::Match(report)
+        } else {
+            Verdict::Mismatch(report)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use comparator::ExactComparator;
+    use image::ImageBuffer;
+    use tests::{rgbw, rbgw};
+
+    #[test]
+    fn test_region_contains() {
+        let region = Region::new(1, 2, 3, 4);
+        assert!(region.contains(1, 2));
+        assert!(region.contains(3, 5));
+        assert!(!region.contains(4, 5));
+        assert!(!region.contains(3, 6));
+        assert!(!region.contains(0, 2));
+    }
+
+    #[test]
+    fn test_masked_region_is_ignored() {
+        let comparator = MaskedComparator::new(ExactComparator)
+            .with_region(Region::new(1, 0, 1, 1))
+            .with_region(Region::new(0, 1, 1, 1));
+        assert!(comparator.compare(&rbgw(), &rgbw()).is_match());
+
+        let comparator = MaskedComparator::new(ExactComparator).with_region(Region::new(0, 1, 1, 1));
+        let verdict = comparator.compare(&rbgw(), &rgbw());
+        assert!(!verdict.is_match());
+        assert_eq!(verdict.report().stats.differing_pixels, 1);
+        assert_eq!(verdict.report().stats.ignored_pixels, 1);
+    }
+
+    #[test]
+    fn test_mask_image() {
+        let mask = DynamicImage::ImageRgba8(ImageBuffer::from_vec(2, 2, vec![
+            0, 0, 0, 0,
+            255, 255, 255, 255,
+            255, 255, 255, 255,
+            0, 0, 0, 255
+        ]).unwrap());
+        let comparator = MaskedComparator::new(ExactComparator).with_mask_image(&mask);
+        assert!(comparator.is_masked(1, 0));
+        assert!(!comparator.is_masked(1, 1));
+        assert!(!comparator.is_masked(5, 5));
+        assert!(comparator.compare(&rbgw(), &rgbw()).is_match());
+    }
+
+    #[test]
+    fn test_find_changed_regions() {
+        let expected = DynamicImage::new_rgba8(6, 4);
+        let mut actual = expected.to_rgba();
+        for (x, y) in &[(0, 0), (1, 1), (4, 0), (4, 1), (5, 3)] {
+            actual.put_pixel(*x, *y, Rgba { data: [255, 255, 255, 255] });
+        }
+        let actual = DynamicImage::ImageRgba8(actual);
+        assert_eq!(find_changed_regions(&actual, &expected, |_, _| false), vec![
+            Region::new(0, 0, 2, 2),
+            Region::new(4, 0, 1, 2),
+            Region::new(5, 3, 1, 1)
+        ]);
+        assert_eq!(find_changed_regions(&actual, &expected, |x, _| x >= 4), vec![Region::new(0, 0, 2, 2)]);
+    }
+
+    #[test]
+    fn test_masked_pixels_hatched_in_diff() {
+        let comparator = MaskedComparator::new(ExactComparator).with_region(Region::new(0, 0, 1, 2));
+        let verdict = comparator.compare(&rbgw(), &rgbw());
+        let diff = verdict.report().diff_image.as_ref().unwrap().to_rgba();
+        assert_eq!(*diff.get_pixel(0, 0), HATCH_COLOUR);
+        assert_eq!(*diff.get_pixel(0, 1), Rgba { data: [0, 0, 0, 0] });
+        assert_eq!(*diff.get_pixel(1, 0), Rgba { data: [0, 0, 255, 255] });
+    }
+}
@@ -0,0 +1,533 @@
+//! Capturing screenshots from the current OpenGL context.
+
+use std::env;
+use std::ffi::CStr;
+use std::os::raw::c_void;
+
+use gl;
+use gl::types::{GLenum, GLint, GLuint};
+use image::{DynamicImage, ImageBuffer, Rgba};
+
+use {CaptureError, ScreenshotCaptor, XrayError, XrayResult};
+
+/// Captures a screenshot using `gl::ReadPixels`
+///
+/// To use this screenshot captor, OpenGL must be able to
+/// load function pointers. If you use Piston or Glutin, this is likely already the case.
+///
+/// If you use a lower level library like `gl` directly, you may need to call
+/// `gl::load_with(|symbol| glfw.get_proc_address(s)))`
+/// or similar, depending on your choice of gl library and context library.
+///
+/// If the capture fails, the returned `CaptureError` names the GL error, and includes the
+/// framebuffer bound for reading and its completeness status.
+///
+/// Screenshots are stored the way they appear on screen, top row first. See `Orientation` for
+/// references captured by earlier versions, which stored them upside down.
+///
+/// By default the screenshot is read from the framebuffer currently bound for reading. Use
+/// `with_source` to read from an offscreen framebuffer object or a texture instead, and
+/// `with_buffer` to capture its depth or stencil buffer rather than its colours.
+///
+/// If the framebuffer being read is multisampled, the captured region is first resolved by
+/// blitting it into a temporary single-sampled framebuffer, since reading multisampled
+/// framebuffers directly fails or returns implementation-defined data. Colours are resolved into
+/// an `RGBA8` renderbuffer and depth and stencil into a `DEPTH24_STENCIL8` renderbuffer, so a
+/// multisampled framebuffer with a different depth format can not be resolved.
+#[derive(Clone, Debug, Default)]
+pub struct OpenGlScreenshotCaptor {
+    orientation: Option<Orientation>,
+    source: CaptureSource,
+    buffer: CaptureBuffer
+}
+
+/// Which buffer of the framebuffer `OpenGlScreenshotCaptor` captures.
+///
+/// Depth and stencil captures are turned into images, so they can be compared against reference
+/// images like any other screenshot.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CaptureBuffer {
+    /// The colour buffer. This is the default.
+    #[default]
+    Color,
+    /// The depth buffer, as a greyscale image. Depths are normalised to the range of depths in the
+    /// captured region, so the nearest pixel is white and the furthest is dark grey. Pixels at the
+    /// far plane, which nothing was drawn over, are black.
+    Depth,
+    /// The stencil buffer, with each stencil value shown in its own colour. Pixels with a stencil
+    /// value of 0 are black.
+    Stencil
+}
+
+/// Where `OpenGlScreenshotCaptor` reads the screenshot from.
+///
+/// Any bindings changed to read from the source are restored once the screenshot has been taken.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CaptureSource {
+    /// The framebuffer and read buffer currently bound for reading. This is the default.
+    #[default]
+    Bound,
+    /// A framebuffer object and the index of the colour attachment to read, e.g. `1` for
+    /// `GL_COLOR_ATTACHMENT1`. The attachment index is ignored for the default framebuffer, `0`.
+    Framebuffer(u32, u32),
+    /// Level 0 of a 2D texture, which is attached to a temporary framebuffer object to be read.
+    /// Depth and stencil captures attach the texture as the depth or depth-stencil attachment.
+    Texture(u32)
+}
+
+/// The environment variable used to select an `Orientation` without changing code.
+pub const ORIENTATION_ENV_VAR: &str = "XRAY_GL_ORIENTATION";
+
+/// The order in which `OpenGlScreenshotCaptor` stores the rows returned by `glReadPixels`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Orientation {
+    /// Stores the top row of the screen first, so screenshots look the same as they did on screen.
+    /// This is the default.
+    #[default]
+    TopDown,
+    /// Stores rows in the order `glReadPixels` returns them, bottom row first, so screenshots are
+    /// upside down. Earlier versions of xray always did this, so use it to keep comparing against
+    /// existing references until they are flipped with `xray flip-references`.
+    BottomUp
+}
+
+impl Orientation {
+    /// Reads the orientation from the `XRAY_GL_ORIENTATION` environment variable, which may be
+    /// `top-down` or `bottom-up`. Defaults to `Orientation::TopDown` if the variable is not set.
+    pub fn from_env() -> Orientation {
+        env::var(ORIENTATION_ENV_VAR)
+            .map(|value| Orientation::parse(&value))
+            .unwrap_or_default()
+    }
+
+    fn parse(value: &str) -> Orientation {
+        match value.trim().to_lowercase().as_str() {
+            "bottom-up" | "bottomup" | "legacy" => Orientation::BottomUp,
+            _ => Orientation::TopDown
+        }
+    }
+}
+
+impl OpenGlScreenshotCaptor {
+    pub fn new() -> OpenGlScreenshotCaptor {
+        OpenGlScreenshotCaptor::default()
+    }
+
+    /// Overrides the orientation set by the `XRAY_GL_ORIENTATION` environment variable.
+    pub fn with_orientation(self, orientation: Orientation) -> OpenGlScreenshotCaptor {
+        OpenGlScreenshotCaptor { orientation: Some(orientation), ..self }
+    }
+
+    /// Reads screenshots from `source` rather than the currently bound framebuffer.
+    pub fn with_source(self, source: CaptureSource) -> OpenGlScreenshotCaptor {
+        OpenGlScreenshotCaptor { source, ..self }
+    }
+
+    /// Captures `buffer` rather than the colour buffer.
+    pub fn with_buffer(self, buffer: CaptureBuffer) -> OpenGlScreenshotCaptor {
+        OpenGlScreenshotCaptor { buffer, ..self }
+    }
+
+    fn orientation(&self) -> Orientation {
+        self.orientation.unwrap_or_else(Orientation::from_env)
+    }
+}
+
+/// The name of a `glGetError` code, e.g. `GL_INVALID_OPERATION`.
+fn gl_error_name(error_code: GLenum) -> String {
+    match error_code {
+        gl::NO_ERROR => "GL_NO_ERROR".to_string(),
+        gl::INVALID_ENUM => "GL_INVALID_ENUM".to_string(),
+        gl::INVALID_VALUE => "GL_INVALID_VALUE".to_string(),
+        gl::INVALID_OPERATION => "GL_INVALID_OPERATION".to_string(),
+        gl::INVALID_FRAMEBUFFER_OPERATION => "GL_INVALID_FRAMEBUFFER_OPERATION".to_string(),
+        gl::OUT_OF_MEMORY => "GL_OUT_OF_MEMORY".to_string(),
+        gl::STACK_UNDERFLOW => "GL_STACK_UNDERFLOW".to_string(),
+        gl::STACK_OVERFLOW => "GL_STACK_OVERFLOW".to_string(),
+        _ => format!("unknown GL error 0x{:04X}", error_code)
+    }
+}
+
+/// The name of a `glCheckFramebufferStatus` result, e.g. `GL_FRAMEBUFFER_INCOMPLETE_ATTACHMENT`.
+fn framebuffer_status_name(status: GLenum) -> String {
+    match status {
+        gl::FRAMEBUFFER_COMPLETE => "GL_FRAMEBUFFER_COMPLETE".to_string(),
+        gl::FRAMEBUFFER_UNDEFINED => "GL_FRAMEBUFFER_UNDEFINED".to_string(),
+        gl::FRAMEBUFFER_INCOMPLETE_ATTACHMENT => "GL_FRAMEBUFFER_INCOMPLETE_ATTACHMENT".to_string(),
+        gl::FRAMEBUFFER_INCOMPLETE_MISSING_ATTACHMENT => "GL_FRAMEBUFFER_INCOMPLETE_MISSING_ATTACHMENT".to_string(),
+        gl::FRAMEBUFFER_INCOMPLETE_DRAW_BUFFER => "GL_FRAMEBUFFER_INCOMPLETE_DRAW_BUFFER".to_string(),
+        gl::FRAMEBUFFER_INCOMPLETE_READ_BUFFER => "GL_FRAMEBUFFER_INCOMPLETE_READ_BUFFER".to_string(),
+        gl::FRAMEBUFFER_UNSUPPORTED => "GL_FRAMEBUFFER_UNSUPPORTED".to_string(),
+        gl::FRAMEBUFFER_INCOMPLETE_MULTISAMPLE => "GL_FRAMEBUFFER_INCOMPLETE_MULTISAMPLE".to_string(),
+        gl::FRAMEBUFFER_INCOMPLETE_LAYER_TARGETS => "GL_FRAMEBUFFER_INCOMPLETE_LAYER_TARGETS".to_string(),
+        0 => "unknown (glCheckFramebufferStatus failed)".to_string(),
+        _ => format!("unknown framebuffer status 0x{:04X}", status)
+    }
+}
+
+/// Discards errors raised by earlier GL calls, so that any error after reading
+/// the pixels was caused by the capture.
+///
+/// Stops after a bounded number of calls, as `glGetError` returns errors forever without a context.
+unsafe fn clear_gl_errors() {
+    for _ in 0..16 {
+        if gl::GetError() == gl::NO_ERROR {
+            break;
+        }
+    }
+}
+
+/// The read state changed while capturing, which is restored when dropped.
+struct SavedReadState {
+    framebuffer: GLint,
+    draw_framebuffer: GLint,
+    renderbuffer: GLint,
+    read_buffer: GLint,
+    pack_alignment: GLint,
+    /// The read buffer of the source framebuffer object, which is part of that framebuffer's state.
+    source_read_buffer: Option<(GLuint, GLint)>,
+    temporary_framebuffers: Vec<GLuint>,
+    temporary_renderbuffer: Option<GLuint>
+}
+
+impl SavedReadState {
+    unsafe fn save() -> SavedReadState {
+        let mut state = SavedReadState {
+            framebuffer: 0,
+            draw_framebuffer: 0,
+            renderbuffer: 0,
+            read_buffer: 0,
+            pack_alignment: 0,
+            source_read_buffer: None,
+            temporary_framebuffers: Vec::new(),
+            temporary_renderbuffer: None
+        };
+        gl::GetIntegerv(gl::READ_FRAMEBUFFER_BINDING, &mut state.framebuffer);
+        gl::GetIntegerv(gl::DRAW_FRAMEBUFFER_BINDING, &mut state.draw_framebuffer);
+        gl::GetIntegerv(gl::RENDERBUFFER_BINDING, &mut state.renderbuffer);
+        gl::GetIntegerv(gl::READ_BUFFER, &mut state.read_buffer);
+        gl::GetIntegerv(gl::PACK_ALIGNMENT, &mut state.pack_alignment);
+        state
+    }
+
+    /// Binds `source` for reading, creating a temporary framebuffer object if it is a texture.
+    unsafe fn bind(&mut self, source: CaptureSource, buffer: CaptureBuffer) {
+        match source {
+            CaptureSource::Bound => {},
+            CaptureSource::Framebuffer(framebuffer, attachment) => {
+                gl::BindFramebuffer(gl::READ_FRAMEBUFFER, framebuffer);
+                if framebuffer != 0 {
+                    let mut read_buffer = 0;
+                    gl::GetIntegerv(gl::READ_BUFFER, &mut read_buffer);
+                    self.source_read_buffer = Some((framebuffer, read_buffer));
+                    gl::ReadBuffer(gl::COLOR_ATTACHMENT0 + attachment);
+                }
+            },
+            CaptureSource::Texture(texture) => {
+                let framebuffer = self.temporary_framebuffer();
+                gl::BindFramebuffer(gl::READ_FRAMEBUFFER, framebuffer);
+                let (attachment, read_buffer) = match buffer {
+                    CaptureBuffer::Color => (gl::COLOR_ATTACHMENT0, gl::COLOR_ATTACHMENT0),
+                    CaptureBuffer::Depth => (gl::DEPTH_ATTACHMENT, gl::NONE),
+                    CaptureBuffer::Stencil => (gl::DEPTH_STENCIL_ATTACHMENT, gl::NONE)
+                };
+                gl::FramebufferTexture2D(gl::READ_FRAMEBUFFER, attachment, gl::TEXTURE_2D, texture, 0);
+                gl::ReadBuffer(read_buffer);
+            }
+        }
+    }
+
+    unsafe fn temporary_framebuffer(&mut self) -> GLuint {
+        let mut framebuffer = 0;
+        gl::GenFramebuffers(1, &mut framebuffer);
+        self.temporary_framebuffers.push(framebuffer);
+        framebuffer
+    }
+
+    /// Whether the framebuffer bound for reading is multisampled.
+    ///
+    /// `GL_SAMPLE_BUFFERS` describes the draw framebuffer, so the read framebuffer is briefly bound
+    /// for drawing too.
+    unsafe fn is_multisampled(&self) -> bool {
+        let mut framebuffer = 0;
+        gl::GetIntegerv(gl::READ_FRAMEBUFFER_BINDING, &mut framebuffer);
+        gl::BindFramebuffer(gl::DRAW_FRAMEBUFFER, framebuffer as GLuint);
+        let mut sample_buffers = 0;
+        gl::GetIntegerv(gl::SAMPLE_BUFFERS, &mut sample_buffers);
+        gl::BindFramebuffer(gl::DRAW_FRAMEBUFFER, self.draw_framebuffer as GLuint);
+        sample_buffers > 0
+    }
+
+    /// If the bound read framebuffer is multisampled, resolves `region` of it into a temporary
+    /// framebuffer, and binds that for reading instead.
+    /// Returns the position of the region in the framebuffer bound for reading.
+    unsafe fn resolve_multisampling(&mut self, (x, y, width, height): (i32, i32, u32, u32), buffer: CaptureBuffer) -> (i32, i32) {
+        if !self.is_multisampled() {
+            return (x, y);
+        }
+        let (internal_format, attachment, mask, read_buffer) = match buffer {
+            CaptureBuffer::Color => (gl::RGBA8, gl::COLOR_ATTACHMENT0, gl::COLOR_BUFFER_BIT, gl::COLOR_ATTACHMENT0),
+            CaptureBuffer::Depth => (gl::DEPTH24_STENCIL8, gl::DEPTH_STENCIL_ATTACHMENT, gl::DEPTH_BUFFER_BIT, gl::NONE),
+            CaptureBuffer::Stencil => (gl::DEPTH24_STENCIL8, gl::DEPTH_STENCIL_ATTACHMENT, gl::STENCIL_BUFFER_BIT, gl::NONE)
+        };
+        let (width, height) = (width as i32, height as i32);
+        let mut renderbuffer = 0;
+        gl::GenRenderbuffers(1, &mut renderbuffer);
+        self.temporary_renderbuffer = Some(renderbuffer);
+        gl::BindRenderbuffer(gl::RENDERBUFFER, renderbuffer);
+        gl::RenderbufferStorage(gl::RENDERBUFFER, internal_format, width, height);
+
+        let resolved = self.temporary_framebuffer();
+        gl::BindFramebuffer(gl::DRAW_FRAMEBUFFER, resolved);
+        gl::FramebufferRenderbuffer(gl::DRAW_FRAMEBUFFER, attachment, gl::RENDERBUFFER, renderbuffer);
+        gl::BlitFramebuffer(x, y, x + width, y + height, 0, 0, width, height, mask, gl::NEAREST);
+        gl::BindFramebuffer(gl::READ_FRAMEBUFFER, resolved);
+        gl::ReadBuffer(read_buffer);
+        (0, 0)
+    }
+}
+
+impl Drop for SavedReadState {
+    fn drop(&mut self) {
+        unsafe {
+            if let Some((framebuffer, read_buffer)) = self.source_read_buffer {
+                gl::BindFramebuffer(gl::READ_FRAMEBUFFER, framebuffer);
+                gl::ReadBuffer(read_buffer as GLenum);
+            }
+            gl::BindFramebuffer(gl::READ_FRAMEBUFFER, self.framebuffer as GLuint);
+            gl::ReadBuffer(self.read_buffer as GLenum);
+            gl::BindFramebuffer(gl::DRAW_FRAMEBUFFER, self.draw_framebuffer as GLuint);
+            gl::BindRenderbuffer(gl::RENDERBUFFER, self.renderbuffer as GLuint);
+            gl::PixelStorei(gl::PACK_ALIGNMENT, self.pack_alignment);
+            for framebuffer in &self.temporary_framebuffers {
+                gl::DeleteFramebuffers(1, framebuffer);
+            }
+            if let Some(renderbuffer) = self.temporary_renderbuffer {
+                gl::DeleteRenderbuffers(1, &renderbuffer);
+            }
+        }
+    }
+}
+
+/// Describes a failed capture of the given region, including the state of the read framebuffer.
+unsafe fn capture_error(error_code: GLenum, x: i32, y: i32, width: u32, height: u32) -> XrayError {
+    let mut binding: GLint = 0;
+    gl::GetIntegerv(gl::READ_FRAMEBUFFER_BINDING, &mut binding);
+    let status = gl::CheckFramebufferStatus(gl::READ_FRAMEBUFFER);
+    clear_gl_errors();
+    XrayError::CaptureError(
+        CaptureError::new(&gl_error_name(error_code), x, y, width, height)
+            .with_framebuffer(binding as u32, &framebuffer_status_name(status))
+    )
+}
+
+/// Reads `components` values of type `T` per pixel from the bound read framebuffer, starting at
+/// `origin`. Errors are reported against the requested `region`.
+unsafe fn read_pixels<T: Clone + Default>(
+    (x, y, width, height): (i32, i32, u32, u32),
+    (read_x, read_y): (i32, i32),
+    components: usize,
+    format: GLenum,
+    pixel_type: GLenum
+) -> XrayResult<Vec<T>> {
+    let mut pixels = vec![T::default(); width as usize * height as usize * components];
+    gl::ReadPixels(read_x, read_y, width as i32, height as i32, format, pixel_type, pixels.as_mut_ptr() as *mut c_void);
+    let error_code = gl::GetError();
+    if error_code != gl::NO_ERROR {
+        return Err(capture_error(error_code, x, y, width, height));
+    }
+    Ok(pixels)
+}
+
+/// Draws depths in the range `[0, 1]` as greyscale, with the nearest depth white and the furthest
+/// dark grey. Depths of 1, at the far plane, are drawn black.
+fn depth_image(width: u32, height: u32, depths: &[f32]) -> DynamicImage {
+    const FURTHEST: f32 = 32.0;
+    let drawn = depths.iter().cloned().filter(|&depth| depth < 1.0);
+    let (near, far) = drawn.fold((1.0f32, 0.0f32), |(near, far), depth| (near.min(depth), far.max(depth)));
+    let pixels = depths.iter().map(|&depth| {
+        if depth >= 1.0 {
+            0
+        } else if far <= near {
+            255
+        } else {
+            (255.0 - (depth - near) / (far - near) * (255.0 - FURTHEST)).round() as u8
+        }
+    }).collect();
+    DynamicImage::ImageLuma8(ImageBuffer::from_vec(width, height, pixels).unwrap())
+}
+
+/// The colour used for a stencil value: black for 0, and well separated hues for other values.
+fn stencil_colour(value: u8) -> Rgba<u8> {
+    if value == 0 {
+        return Rgba { data: [0, 0, 0, 255] };
+    }
+    // Stepping the hue by the golden ratio keeps consecutive values far apart.
+    let hue = (f64::from(value) * 0.618_033_988_75).fract() * 6.0;
+    #[allow(clippy::manual_is_multiple_of)] // is_multiple_of needs Rust 1.87
+    let lightness = if value % 2 == 0 { 255.0 } else { 192.0 };
+    let fraction = hue.fract();
+    let (r, g, b) = match hue as u32 {
+        0 => (1.0, fraction, 0.0),
+        1 => (1.0 - fraction, 1.0, 0.0),
+        2 => (0.0, 1.0, fraction),
+        3 => (0.0, 1.0 - fraction, 1.0),
+        4 => (fraction, 0.0, 1.0),
+        _ => (1.0, 0.0, 1.0 - fraction)
+    };
+    let channel = |component: f64| (component * lightness).round() as u8;
+    Rgba { data: [channel(r), channel(g), channel(b), 255] }
+}
+
+fn stencil_image(width: u32, height: u32, values: &[u8]) -> DynamicImage {
+    DynamicImage::ImageRgba8(ImageBuffer::from_fn(width, height, |x, y| {
+        stencil_colour(values[(y * width + x) as usize])
+    }))
+}
+
+/// Substrings of `GL_RENDERER` or `GL_VENDOR` which identify a renderer, and the variant key used for it.
+const RENDERER_VARIANT_KEYS: &[(&str, &str)] = &[
+    ("llvmpipe", "llvmpipe"),
+    ("softpipe", "softpipe"),
+    ("swiftshader", "swiftshader"),
+    ("nvidia", "nvidia"),
+    ("geforce", "nvidia"),
+    ("intel", "intel"),
+    ("amd", "amd"),
+    ("radeon", "amd"),
+    ("ati technologies", "amd"),
+    ("apple", "apple")
+];
+
+fn variant_keys(vendor: &str, renderer: &str) -> Vec<String> {
+    let mut keys: Vec<String> = Vec::new();
+    for name in &[renderer.to_lowercase(), vendor.to_lowercase()] {
+        for &(pattern, key) in RENDERER_VARIANT_KEYS {
+            if name.contains(pattern) && !keys.iter().any(|existing| existing == key) {
+                keys.push(key.to_string());
+            }
+        }
+    }
+    keys
+}
+
+unsafe fn gl_string(name: GLenum) -> Option<String> {
+    let value = gl::GetString(name);
+    if value.is_null() {
+        None
+    } else {
+        Some(CStr::from_ptr(value as *const _).to_string_lossy().into_owned())
+    }
+}
+
+/// The reference variant keys for the renderer of the current GL context, such as `llvmpipe` for Mesa's
+/// software renderer, or `nvidia`, `intel` or `amd` for hardware renderers. Empty if no context is current.
+pub(crate) fn renderer_variant_keys() -> Vec<String> {
+    if !gl::GetString::is_loaded() {
+        return Vec::new();
+    }
+    unsafe {
+        let keys = match (gl_string(gl::VENDOR), gl_string(gl::RENDERER)) {
+            (Some(vendor), Some(renderer)) => variant_keys(&vendor, &renderer),
+            _ => Vec::new()
+        };
+        clear_gl_errors();
+        keys
+    }
+}
+
+impl ScreenshotCaptor for OpenGlScreenshotCaptor {
+    fn capture_image(&self, x: i32, y: i32, width: u32, height: u32) -> XrayResult<DynamicImage> {
+        let region = (x, y, width, height);
+        let img = unsafe {
+            clear_gl_errors();
+            let mut saved_state = SavedReadState::save();
+            saved_state.bind(self.source, self.buffer);
+            let origin = saved_state.resolve_multisampling(region, self.buffer);
+            gl::PixelStorei(gl::PACK_ALIGNMENT, 1);
+            match self.buffer {
+                CaptureBuffer::Color => {
+                    let pixels = read_pixels(region, origin, 4, gl::RGBA, gl::UNSIGNED_BYTE)?;
+                    DynamicImage::ImageRgba8(ImageBuffer::from_vec(width, height, pixels).unwrap())
+                },
+                CaptureBuffer::Depth => {
+                    depth_image(width, height, &read_pixels(region, origin, 1, gl::DEPTH_COMPONENT, gl::FLOAT)?)
+                },
+                CaptureBuffer::Stencil => {
+                    stencil_image(width, height, &read_pixels(region, origin, 1, gl::STENCIL_INDEX, gl::UNSIGNED_BYTE)?)
+                }
+            }
+        };
+
+        Ok(match self.orientation() {
+            Orientation::TopDown => img.flipv(),
+            Orientation::BottomUp => img
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_variant_keys() {
+        assert_eq!(variant_keys("Mesa", "llvmpipe (LLVM 15.0.7, 256 bits)"), vec!["llvmpipe"]);
+        assert_eq!(variant_keys("Intel", "Mesa Intel(R) UHD Graphics 620 (KBL GT2)"), vec!["intel"]);
+        assert_eq!(variant_keys("NVIDIA Corporation", "NVIDIA GeForce RTX 3070/PCIe/SSE2"), vec!["nvidia"]);
+        assert_eq!(variant_keys("ATI Technologies Inc.", "AMD Radeon Pro 560X OpenGL Engine"), vec!["amd"]);
+        assert!(variant_keys("Unknown", "Unknown").is_empty());
+    }
+
+    #[test]
+    fn test_gl_error_names() {
+        assert_eq!(gl_error_name(gl::INVALID_OPERATION), "GL_INVALID_OPERATION");
+        assert_eq!(gl_error_name(gl::INVALID_VALUE), "GL_INVALID_VALUE");
+        assert_eq!(gl_error_name(0x1234), "unknown GL error 0x1234");
+        assert_eq!(framebuffer_status_name(gl::FRAMEBUFFER_INCOMPLETE_ATTACHMENT), "GL_FRAMEBUFFER_INCOMPLETE_ATTACHMENT");
+    }
+
+    #[test]
+    fn test_orientation_parse() {
+        assert_eq!(Orientation::parse("bottom-up"), Orientation::BottomUp);
+        assert_eq!(Orientation::parse(" Legacy "), Orientation::BottomUp);
+        assert_eq!(Orientation::parse("top-down"), Orientation::TopDown);
+        assert_eq!(Orientation::parse(""), Orientation::TopDown);
+        let captor = OpenGlScreenshotCaptor::new()
+            .with_source(CaptureSource::Texture(7))
+            .with_orientation(Orientation::BottomUp);
+        assert_eq!(captor.orientation(), Orientation::BottomUp);
+        assert_eq!(captor.source, CaptureSource::Texture(7));
+    }
+
+    #[test]
+    fn test_depth_image() {
+        let image = depth_image(2, 2, &[0.25, 0.5, 0.75, 1.0]);
+        assert_eq!(image.to_luma().into_raw(), vec![255, 144, 32, 0]);
+        assert_eq!(depth_image(1, 1, &[0.5]).to_luma().into_raw(), vec![255]);
+    }
+
+    #[test]
+    fn test_stencil_colours() {
+        assert_eq!(stencil_colour(0), Rgba { data: [0, 0, 0, 255] });
+        let colours: Vec<Rgba<u8>> = (0..=255).map(stencil_colour).collect();
+        for (value, colour) in colours.iter().enumerate().skip(1) {
+            assert_ne!(*colour, colours[value - 1]);
+            assert_ne!(*colour, colours[0]);
+        }
+        assert_eq!(stencil_image(2, 1, &[0, 1]).to_rgba().get_pixel(1, 0), &stencil_colour(1));
+    }
+
+    #[test]
+    fn test_capture_error_message() {
+        let err = XrayError::CaptureError(
+            CaptureError::new("GL_INVALID_OPERATION", 0, 0, 1280, 720)
+                .with_framebuffer(3, &framebuffer_status_name(gl::FRAMEBUFFER_INCOMPLETE_ATTACHMENT))
+        );
+        assert_eq!(
+            err.to_string(),
+            "Could not take screenshot of 1280x720 region at (0, 0): GL_INVALID_OPERATION\n\
+             Read framebuffer 3: GL_FRAMEBUFFER_INCOMPLETE_ATTACHMENT"
+        );
+    }
+}
@@ -0,0 +1,456 @@
+//! A static HTML report of every screenshot test which wrote output to a directory.
+//!
+//! Each test records its outcome in `<output_path>/<test_name>/result.json`, and once all tests have run,
+//! the report at `<output_path>/index.html` is built from these records and the images written for failed tests.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+use image::{DynamicImage, GenericImage};
+use serde_json;
+
+use comparator::{ComparisonReport, DiffStats};
+use mask::Region;
+use {relative_file_paths, ScreenshotError, XrayError};
+
+/// The name of the file in a test's output directory which records its outcome, as JSON.
+pub const RESULT_FILE_NAME: &str = "result.json";
+
+/// The name of the HTML report written to the top of the output directory.
+pub const REPORT_FILE_NAME: &str = "index.html";
+
+/// Images written by failed tests, in the order they are shown in the report.
+/// Any other images (such as debug images from the comparator) are shown after these.
+const STANDARD_IMAGES: [&str; 3] = ["expected.png", "actual.png", "diff.png"];
+
+/// The outcome of a single screenshot test.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum TestStatus {
+    /// The screenshot matched the reference image.
+    Passed,
+    /// The screenshot did not match the reference image.
+    Failed,
+    /// There was no reference image to compare the screenshot to.
+    NoReference,
+    /// The reference image was rewritten from the screenshot (see `UpdateMode`).
+    Updated,
+    /// The screenshot could not be taken, or the reference image could not be loaded.
+    Error
+}
+
+impl TestStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TestStatus::Passed => "passed",
+            TestStatus::Failed => "failed",
+            TestStatus::NoReference => "no-reference",
+            TestStatus::Updated => "updated",
+            TestStatus::Error => "error"
+        }
+    }
+
+    /// Whether a test with this status fails.
+    pub fn is_failure(&self) -> bool {
+        match self {
+            TestStatus::Passed | TestStatus::Updated => false,
+            TestStatus::Failed | TestStatus::NoReference | TestStatus::Error => true
+        }
+    }
+
+    pub(crate) fn for_error(error: &XrayError) -> TestStatus {
+        match error {
+            XrayError::Screenshot(ScreenshotError::NoReferenceScreenshot(_)) => TestStatus::NoReference,
+            XrayError::Screenshot(ScreenshotError::ScreenshotMismatch(..)) => TestStatus::Failed,
+            _ => TestStatus::Error
+        }
+    }
+}
+
+/// Serialises a `Duration` as a number of seconds.
+mod seconds {
+    use serde::{Deserialize, Deserializer, Serializer};
+    use std::time::Duration;
+
+    pub fn serialize<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_f64(duration.as_secs_f64())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+        Ok(Duration::from_secs_f64(f64::deserialize(deserializer)?.max(0.0)))
+    }
+}
+
+/// How long each stage of a screenshot test took. Recorded in seconds in `result.json`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Timings {
+    /// Capturing the screenshot with the `ScreenshotCaptor`.
+    #[serde(rename = "capture_seconds", with = "seconds")]
+    pub capture: Duration,
+    /// Comparing the screenshot with the reference image using the `ImageComparator`.
+    #[serde(rename = "compare_seconds", with = "seconds")]
+    pub compare: Duration,
+    /// The whole test, including loading the reference image.
+    #[serde(rename = "total_seconds", with = "seconds")]
+    pub total: Duration
+}
+
+/// The width and height of an image, in pixels.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ImageSize {
+    pub width: u32,
+    pub height: u32
+}
+
+impl ImageSize {
+    pub fn of(image: &DynamicImage) -> ImageSize {
+        let (width, height) = image.dimensions();
+        ImageSize { width, height }
+    }
+}
+
+/// The outcome of a screenshot test, passed to `ScreenshotIo::write_result` once the test has finished.
+/// 
+/// `FsScreenshotIo` writes it to `<output_path>/<test_name>/result.json`, so that tools can read the
+/// results of a test run without comparing the images again. Details which are not known, such as the
+/// comparison statistics of a test which had no reference image, are left empty.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TestRecord {
+    /// The name of the test. `ScreenshotIo` implementations which know the test name fill this in.
+    #[serde(default)]
+    pub test_name: String,
+    pub status: TestStatus,
+    /// A human readable description of the outcome, such as the comparator's summary of the differences.
+    pub summary: String,
+    /// The name of the comparator used (see `ComparisonReport::comparator`).
+    #[serde(default)]
+    pub comparator: Option<String>,
+    /// The settings the comparator used to decide the verdict (see `ComparisonReport::thresholds`).
+    #[serde(default)]
+    pub thresholds: BTreeMap<String, f64>,
+    /// Statistics on the pixels which differed, including the number of differing pixels
+    /// and the largest channel difference.
+    #[serde(default)]
+    pub stats: Option<DiffStats>,
+    /// The comparator's overall similarity score, if it produces one.
+    #[serde(default)]
+    pub score: Option<f64>,
+    /// The bounding boxes of the areas of the screenshot which differ from the reference image at all,
+    /// ignoring any masked pixels. Empty if the images are identical or there was no reference image.
+    #[serde(default)]
+    pub changed_regions: Vec<Region>,
+    /// The size of the captured screenshot.
+    #[serde(default)]
+    pub actual_size: Option<ImageSize>,
+    /// The size of the reference image.
+    #[serde(default)]
+    pub expected_size: Option<ImageSize>,
+    /// The key of the reference variant the screenshot was compared with (see `FsScreenshotIo`),
+    /// or `None` if it was compared with the test's own reference image.
+    #[serde(default)]
+    pub reference_variant: Option<String>,
+    #[serde(default)]
+    pub timings: Timings
+}
+
+impl TestRecord {
+    pub fn new(status: TestStatus, summary: &str) -> TestRecord {
+        TestRecord {
+            test_name: String::new(),
+            status,
+            summary: summary.to_string(),
+            comparator: None,
+            thresholds: BTreeMap::new(),
+            stats: None,
+            score: None,
+            changed_regions: Vec::new(),
+            actual_size: None,
+            expected_size: None,
+            reference_variant: None,
+            timings: Timings::default()
+        }
+    }
+
+    /// Copies the comparator's name, thresholds, statistics and score from `report`.
+    pub fn set_report(&mut self, report: &ComparisonReport) {
+        self.comparator = Some(report.comparator.clone());
+        self.thresholds = report.thresholds.iter().cloned().collect();
+        self.stats = Some(report.stats.clone());
+        self.score = report.score;
+    }
+
+    pub(crate) fn to_json(&self) -> String {
+        // Serialising plain data with string keys cannot fail.
+        serde_json::to_string_pretty(self).unwrap_or_default()
+    }
+
+    pub(crate) fn from_json(json: &str) -> Option<TestRecord> {
+        serde_json::from_str(json).ok()
+    }
+}
+
+/// Lists the name and outcome of every test which recorded a result under `output_path`, sorted by name.
+/// Result files which cannot be read or parsed are skipped.
+pub fn find_test_records<P: AsRef<Path>>(output_path: P) -> io::Result<Vec<(String, TestRecord)>> {
+    let output_path = output_path.as_ref();
+    let suffix = format!("/{}", RESULT_FILE_NAME);
+    Ok(relative_file_paths(output_path)?.into_iter()
+        .filter(|path| path.ends_with(&suffix))
+        .filter_map(|path| {
+            let record = fs::read_to_string(output_path.join(&path)).ok()
+                .and_then(|json| TestRecord::from_json(&json))?;
+            Some((path[..path.len() - suffix.len()].to_string(), record))
+        })
+        .collect())
+}
+
+/// Reads the outcome recorded by the test `test_name` under `output_path`, if it recorded one which can be parsed.
+pub fn read_test_record<P: AsRef<Path>>(output_path: P, test_name: &str) -> Option<TestRecord> {
+    let result_path = output_path.as_ref().join(test_name).join(RESULT_FILE_NAME);
+    fs::read_to_string(result_path).ok().and_then(|json| TestRecord::from_json(&json))
+}
+
+/// Rebuilds `<output_path>/index.html` from the results recorded under `output_path`.
+///
+/// The report links to the images in each test's output directory by relative path, so the
+/// whole output directory should be kept (or published as a CI artifact) together with it.
+pub fn write_html_report<P: AsRef<Path>>(output_path: P) -> io::Result<()> {
+    let output_path = output_path.as_ref();
+    let html = render_html_report(output_path, &find_test_records(output_path)?)?;
+    write_report_file(output_path, REPORT_FILE_NAME, &html)
+}
+
+/// Writes a report to `<output_path>/<file_name>`.
+/// 
+/// The report is written to a temporary file which then atomically replaces the report, so
+/// that it is never seen half written.
+pub(crate) fn write_report_file(output_path: &Path, file_name: &str, contents: &str) -> io::Result<()> {
+    static REPORT_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+    fs::create_dir_all(output_path)?;
+    let temp_path = output_path.join(format!(
+        ".{}.{}-{}.tmp",
+        file_name,
+        process::id(),
+        REPORT_COUNTER.fetch_add(1, Ordering::SeqCst)
+    ));
+    fs::write(&temp_path, contents)?;
+    fs::rename(&temp_path, output_path.join(file_name))
+}
+
+/// Escapes text for use in HTML or XML content and attribute values.
+pub(crate) fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c)
+        }
+    }
+    escaped
+}
+
+/// Percent-encodes the characters of a relative path which have a special meaning in URLs.
+fn encode_path(path: &str) -> String {
+    let mut encoded = String::with_capacity(path.len());
+    for byte in path.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'/' | b'-' | b'_' | b'.' | b'~' => encoded.push(byte as char),
+            _ => encoded.push_str(&format!("%{:02X}", byte))
+        }
+    }
+    encoded
+}
+
+/// Lists the images in a test's output directory, standard images first.
+fn test_images(test_output: &Path) -> io::Result<Vec<String>> {
+    let mut extra_images = Vec::new();
+    if test_output.is_dir() {
+        for entry in fs::read_dir(test_output)? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().to_string();
+            if entry.file_type()?.is_file() && name.ends_with(".png") && !STANDARD_IMAGES.contains(&name.as_str()) {
+                extra_images.push(name);
+            }
+        }
+    }
+    extra_images.sort();
+    Ok(STANDARD_IMAGES.iter()
+        .map(|name| name.to_string())
+        .filter(|name| test_output.join(name).is_file())
+        .chain(extra_images)
+        .collect())
+}
+
+fn render_test(output_path: &Path, test_name: &str, record: &TestRecord) -> io::Result<String> {
+    let status = record.status.as_str();
+    let mut html = format!(
+        "<section class=\"test {status}\" id=\"{id}\">\n<h2><span class=\"status\">{status}</span> {name} <small>{seconds:.3}s</small></h2>\n<pre>{summary}</pre>\n",
+        status = status,
+        seconds = record.timings.total.as_secs_f64(),
+        id = escape_html(test_name),
+        name = escape_html(test_name),
+        summary = escape_html(&record.summary)
+    );
+    if let Some(ref variant) = record.reference_variant {
+        html.push_str(&format!("<p>Compared with the reference variant <code>{}</code>.</p>\n", escape_html(variant)));
+    }
+    // Passing tests do not write images, so any images present are left over from an earlier failure.
+    if record.status.is_failure() {
+        let images = test_images(&output_path.join(test_name))?;
+        let src = |image: &str| encode_path(&format!("{}/{}", test_name, image));
+        if images.iter().any(|image| image == "expected.png") && images.iter().any(|image| image == "actual.png") {
+            html.push_str(&format!(concat!(
+                "<div class=\"compare\">\n",
+                "<div class=\"stack\"><img src=\"{expected}\" alt=\"expected\"><img class=\"top\" src=\"{actual}\" alt=\"actual\"></div>\n",
+                "<div class=\"controls\"><select><option value=\"slider\">Slider</option><option value=\"onion\">Onion skin</option></select>",
+                " expected <input type=\"range\" min=\"0\" max=\"100\" value=\"50\"> actual</div>\n",
+                "</div>\n"),
+                expected = src("expected.png"),
+                actual = src("actual.png")
+            ));
+        }
+        html.push_str("<div class=\"thumbnails\">\n");
+        for image in &images {
+            html.push_str(&format!(
+                "<figure><a href=\"{src}\"><img src=\"{src}\" alt=\"{name}\"></a><figcaption>{name}</figcaption></figure>\n",
+                src = src(image),
+                name = escape_html(&image[..image.len() - ".png".len()])
+            ));
+        }
+        html.push_str("</div>\n");
+    }
+    html.push_str("</section>\n");
+    Ok(html)
+}
+
+const REPORT_STYLE: &str = "
+body { font-family: sans-serif; margin: 2em; background: #f4f4f4; color: #222; }
+body.hide-passed .test.passed, body.hide-passed .test.updated { display: none; }
+.test { background: #fff; border-left: 6px solid #999; margin: 1em 0; padding: 0.5em 1em; }
+.test.passed, .test.updated { border-color: #2a2; }
+.test.failed, .test.no-reference, .test.error { border-color: #c22; }
+.status { font-size: 0.7em; text-transform: uppercase; padding: 0.2em 0.5em; background: #eee; }
+pre { white-space: pre-wrap; }
+img { image-rendering: pixelated; background: repeating-conic-gradient(#ccc 0 25%, #fff 0 50%) 0 0 / 16px 16px; }
+.thumbnails { display: flex; flex-wrap: wrap; gap: 1em; }
+.thumbnails figure { margin: 0; }
+.thumbnails img { max-width: 240px; max-height: 240px; border: 1px solid #ccc; }
+.thumbnails figcaption { text-align: center; }
+.compare { margin-bottom: 1em; }
+.stack { position: relative; display: inline-block; }
+.stack img { display: block; max-width: 640px; width: 100%; }
+.stack img.top { position: absolute; left: 0; top: 0; }
+";
+
+const REPORT_SCRIPT: &str = "
+document.getElementById('show-passed').addEventListener('change', function (event) {
+    document.body.classList.toggle('hide-passed', !event.target.checked);
+});
+document.querySelectorAll('.compare').forEach(function (compare) {
+    var top = compare.querySelector('.top');
+    var range = compare.querySelector('input');
+    var mode = compare.querySelector('select');
+    function update() {
+        if (mode.value === 'slider') {
+            top.style.opacity = 1;
+            top.style.clipPath = 'inset(0 ' + (100 - range.value) + '% 0 0)';
+        } else {
+            top.style.clipPath = 'none';
+            top.style.opacity = range.value / 100;
+        }
+    }
+    range.addEventListener('input', update);
+    mode.addEventListener('change', update);
+    update();
+});
+";
+
+fn render_html_report(output_path: &Path, records: &[(String, TestRecord)]) -> io::Result<String> {
+    let failures = records.iter().filter(|(_, record)| record.status.is_failure()).count();
+    let mut html = format!(concat!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>xray screenshot tests</title>\n",
+        "<style>{style}</style>\n</head>\n<body>\n",
+        "<h1>Screenshot tests: {failures} failed, {passes} passed</h1>\n",
+        "<label><input type=\"checkbox\" id=\"show-passed\" checked> Show passed tests</label>\n"),
+        style = REPORT_STYLE,
+        failures = failures,
+        passes = records.len() - failures
+    );
+    // Failures first, so they are seen without scrolling past every passing test.
+    for failure_group in &[true, false] {
+        for (test_name, record) in records.iter().filter(|(_, record)| record.status.is_failure() == *failure_group) {
+            html.push_str(&render_test(output_path, test_name, record)?);
+        }
+    }
+    html.push_str(&format!("<script>{}</script>\n</body>\n</html>\n", REPORT_SCRIPT));
+    Ok(html)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use write_png_creating_dirs;
+    use tests::rgbw;
+
+    #[test]
+    fn test_record_round_trip() {
+        let mut record = TestRecord::new(TestStatus::Failed, "Did not match.\nSecond line");
+        record.set_report(&ComparisonReport::new("exact", DiffStats {
+            total_pixels: 4,
+            differing_pixels: 1,
+            allowed_differing_pixels: 0,
+            max_channel_delta: 12,
+            ignored_pixels: 0
+        }, String::new()).with_threshold("max_channel_delta", 0.0));
+        record.changed_regions.push(Region::new(1, 0, 1, 1));
+        record.timings.total = Duration::from_millis(1500);
+        let json = record.to_json();
+        assert!(json.contains("\"status\": \"failed\""));
+        assert!(json.contains("\"total_seconds\": 1.5"));
+        assert!(json.contains("\"max_channel_delta\": 12"));
+        assert_eq!(TestRecord::from_json(&json), Some(record));
+        assert_eq!(TestRecord::from_json("{\"status\": \"unknown\", \"summary\": \"\"}"), None);
+    }
+
+    #[test]
+    fn test_escaping() {
+        assert_eq!(escape_html("<a href=\"x\">&</a>"), "&lt;a href=&quot;x&quot;&gt;&amp;&lt;/a&gt;");
+        assert_eq!(encode_path("menus/main menu#1.png"), "menus/main%20menu%231.png");
+    }
+
+    #[test]
+    fn test_html_report() {
+        let output = ::std::env::temp_dir().join(format!("xray-report-test-{}", process::id()));
+        let record = |status, summary| TestRecord::new(status, summary).to_json();
+        fs::create_dir_all(output.join("intro")).unwrap();
+        fs::write(output.join("intro").join(RESULT_FILE_NAME), record(TestStatus::Passed, "All good")).unwrap();
+        fs::create_dir_all(output.join("menus/main")).unwrap();
+        fs::write(output.join("menus/main").join(RESULT_FILE_NAME), record(TestStatus::Failed, "1 <pixel>")).unwrap();
+        for image in &["expected.png", "actual.png", "diff.png", "similarity.png"] {
+            write_png_creating_dirs(&output.join("menus/main").join(image), &rgbw()).unwrap();
+        }
+
+        let records = find_test_records(&output).unwrap();
+        assert_eq!(records.iter().map(|(name, _)| name.as_str()).collect::<Vec<_>>(), vec!["intro", "menus/main"]);
+
+        write_html_report(&output).unwrap();
+        let html = fs::read_to_string(output.join(REPORT_FILE_NAME)).unwrap();
+        assert!(html.contains("1 failed, 1 passed"));
+        assert!(html.contains("1 &lt;pixel&gt;"));
+        assert!(html.contains("<img class=\"top\" src=\"menus/main/actual.png\""));
+        assert!(html.contains("menus/main/similarity.png"));
+        assert!(html.find("menus/main").unwrap() < html.find("intro").unwrap());
+
+        fs::remove_dir_all(&output).unwrap();
+    }
+}
@@ -0,0 +1,323 @@
+//! Structural similarity (SSIM) comparison of screenshots.
+//!
+//! SSIM compares the local brightness, contrast and structure of two images rather than
+//! individual pixel values, so it is tolerant of dithering, noise and post-processing
+//! which would fail a pixel by pixel comparison.
+
+use image::{DynamicImage, GenericImage, ImageBuffer, Luma, RgbaImage};
+
+use comparator::{channel_delta, compare_images, rgb_to_yiq, ComparisonReport, DiffStats, ImageComparator, Tolerance, Verdict};
+
+const C1: f64 = (0.01 * 255.0) * (0.01 * 255.0);
+const C2: f64 = (0.03 * 255.0) * (0.03 * 255.0);
+
+/// Weights for each scale of MS-SSIM, from Wang, Simoncelli and Bovik (2003).
+const MS_SSIM_WEIGHTS: [f64; 5] = [0.0448, 0.2856, 0.3001, 0.2363, 0.1333];
+
+/// The brightness of each pixel of an image, blended against white.
+struct LumaPlane {
+    width: u32,
+    height: u32,
+    data: Vec<f64>
+}
+
+impl LumaPlane {
+    fn from_image(image: &RgbaImage) -> LumaPlane {
+        LumaPlane {
+            width: image.width(),
+            height: image.height(),
+            data: image.pixels().map(|pixel| rgb_to_yiq(*pixel).0).collect()
+        }
+    }
+
+    /// Halves the size of the plane by averaging each 2x2 block of pixels.
+    fn downsample(&self) -> LumaPlane {
+        let width = self.width / 2;
+        let height = self.height / 2;
+        let mut data = Vec::with_capacity((width * height) as usize);
+        for y in 0..height {
+            for x in 0..width {
+                let at = |dx: u32, dy: u32| self.data[((y * 2 + dy) * self.width + x * 2 + dx) as usize];
+                data.push((at(0, 0) + at(1, 0) + at(0, 1) + at(1, 1)) / 4.0);
+            }
+        }
+        LumaPlane { width, height, data }
+    }
+}
+
+/// A summed area table, allowing the sum of any rectangle of values to be found in constant time.
+struct SummedArea {
+    stride: usize,
+    sums: Vec<f64>
+}
+
+impl SummedArea {
+    fn new<F: Fn(usize) -> f64>(width: u32, height: u32, value: F) -> SummedArea {
+        let stride = width as usize + 1;
+        let mut sums = vec![0.0; stride * (height as usize + 1)];
+        for y in 0..height as usize {
+            let mut row_sum = 0.0;
+            for x in 0..width as usize {
+                row_sum += value(y * width as usize + x);
+                sums[(y + 1) * stride + x + 1] = sums[y * stride + x + 1] + row_sum;
+            }
+        }
+        SummedArea { stride, sums }
+    }
+
+    fn sum(&self, x0: usize, y0: usize, x1: usize, y1: usize) -> f64 {
+        self.sums[y1 * self.stride + x1] - self.sums[y0 * self.stride + x1]
+            - self.sums[y1 * self.stride + x0] + self.sums[y0 * self.stride + x0]
+    }
+}
+
+/// The luminance and contrast-structure components of SSIM, for a window centred on each pixel.
+struct SsimMaps {
+    luminance: Vec<f64>,
+    contrast_structure: Vec<f64>
+}
+
+impl SsimMaps {
+    fn ssim(&self) -> Vec<f64> {
+        self.luminance.iter().zip(self.contrast_structure.iter()).map(|(l, cs)| l * cs).collect()
+    }
+}
+
+fn mean(values: &[f64]) -> f64 {
+    if values.is_empty() {
+        1.0
+    } else {
+        values.iter().sum::<f64>() / values.len() as f64
+    }
+}
+
+fn ssim_maps(a: &LumaPlane, b: &LumaPlane, window_size: u32) -> SsimMaps {
+    let (width, height) = (a.width, a.height);
+    let sum_a = SummedArea::new(width, height, |i| a.data[i]);
+    let sum_b = SummedArea::new(width, height, |i| b.data[i]);
+    let sum_aa = SummedArea::new(width, height, |i| a.data[i] * a.data[i]);
+    let sum_bb = SummedArea::new(width, height, |i| b.data[i] * b.data[i]);
+    let sum_ab = SummedArea::new(width, height, |i| a.data[i] * b.data[i]);
+
+    let half = window_size / 2;
+    let pixels = (width * height) as usize;
+    let mut luminance = Vec::with_capacity(pixels);
+    let mut contrast_structure = Vec::with_capacity(pixels);
+    for y in 0..height {
+        for x in 0..width {
+            let x0 = x.saturating_sub(half) as usize;
+            let y0 = y.saturating_sub(half) as usize;
+            let x1 = (x + window_size - half).min(width) as usize;
+            let y1 = (y + window_size - half).min(height) as usize;
+            let n = ((x1 - x0) * (y1 - y0)) as f64;
+
+            let mean_a = sum_a.sum(x0, y0, x1, y1) / n;
+            let mean_b = sum_b.sum(x0, y0, x1, y1) / n;
+            let variance_a = (sum_aa.sum(x0, y0, x1, y1) / n - mean_a * mean_a).max(0.0);
+            let variance_b = (sum_bb.sum(x0, y0, x1, y1) / n - mean_b * mean_b).max(0.0);
+            let covariance = sum_ab.sum(x0, y0, x1, y1) / n - mean_a * mean_b;
+
+            luminance.push((2.0 * mean_a * mean_b + C1) / (mean_a * mean_a + mean_b * mean_b + C1));
+            contrast_structure.push((2.0 * covariance + C2) / (variance_a + variance_b + C2));
+        }
+    }
+    SsimMaps { luminance, contrast_structure }
+}
+
+/// Combines SSIM computed over successively halved copies of the images, capturing
+/// differences in structure at several levels of detail.
+fn ms_ssim_score(a: &LumaPlane, b: &LumaPlane, window_size: u32, full_size_maps: &SsimMaps) -> f64 {
+    let mut contrast_structure_means = vec![mean(&full_size_maps.contrast_structure)];
+    let mut last_ssim = mean(&full_size_maps.ssim());
+    let mut scaled_a = a.downsample();
+    let mut scaled_b = b.downsample();
+    while contrast_structure_means.len() < MS_SSIM_WEIGHTS.len() && scaled_a.width.min(scaled_a.height) >= window_size {
+        let maps = ssim_maps(&scaled_a, &scaled_b, window_size);
+        contrast_structure_means.push(mean(&maps.contrast_structure));
+        last_ssim = mean(&maps.ssim());
+        scaled_a = scaled_a.downsample();
+        scaled_b = scaled_b.downsample();
+    }
+
+    let scales = contrast_structure_means.len();
+    let weights = &MS_SSIM_WEIGHTS[..scales];
+    let total_weight: f64 = weights.iter().sum();
+    let coarse_scales = contrast_structure_means[..scales - 1].iter()
+        .zip(weights.iter())
+        .map(|(cs, weight)| cs.max(0.0).powf(weight / total_weight))
+        .product::<f64>();
+    coarse_scales * last_ssim.max(0.0).powf(weights[scales - 1] / total_weight)
+}
+
+fn similarity_map_image(width: u32, height: u32, ssim: &[f64]) -> DynamicImage {
+    DynamicImage::ImageLuma8(ImageBuffer::from_fn(width, height, |x, y| {
+        let similarity = ssim[(y * width + x) as usize].clamp(0.0, 1.0);
+        Luma { data: [(similarity * 255.0).round() as u8] }
+    }))
+}
+
+/// Compares images by their structural similarity (SSIM), passing if the overall similarity score
+/// is at least `threshold`.
+///
+/// Scores range from 1.0 for identical images down towards 0.0 (or below, for inverted images).
+/// The default threshold is 0.98. The similarity is measured over a sliding window of
+/// `window_size` pixels square, defaulting to 8, using the brightness of each pixel.
+///
+/// If `multi_scale` is set, the score is computed using MS-SSIM, which also compares successively
+/// halved copies of the images, making it less sensitive to fine noise.
+///
+/// On a mismatch, a similarity map is provided as the debug image `similarity`, in which each pixel is
+/// the local similarity around that point, from black (dissimilar) to white (identical).
+/// `DiffStats::differing_pixels` counts the pixels whose local similarity is below `threshold`,
+/// but only the overall score decides whether the images match.
+#[derive(Clone, Copy, Debug)]
+pub struct SsimComparator {
+    pub threshold: f64,
+    pub window_size: u32,
+    pub multi_scale: bool
+}
+
+impl SsimComparator {
+    pub fn new(threshold: f64) -> SsimComparator {
+        SsimComparator {
+            threshold,
+            window_size: 8,
+            multi_scale: false
+        }
+    }
+
+    /// Measures local similarity over windows of `window_size` pixels square.
+    pub fn with_window_size(self, window_size: u32) -> SsimComparator {
+        SsimComparator { window_size: window_size.max(1), ..self }
+    }
+
+    /// Uses MS-SSIM rather than single scale SSIM to compute the overall score.
+    pub fn with_multi_scale(self, multi_scale: bool) -> SsimComparator {
+        SsimComparator { multi_scale, ..self }
+    }
+
+    fn name(&self) -> &'static str {
+        if self.multi_scale { "ms-ssim" } else { "ssim" }
+    }
+
+    fn new_report(&self, stats: DiffStats, summary: String) -> ComparisonReport {
+        ComparisonReport::new(self.name(), stats, summary)
+            .with_threshold("threshold", self.threshold)
+            .with_threshold("window_size", f64::from(self.window_size))
+    }
+}
+
+impl Default for SsimComparator {
+    fn default() -> SsimComparator {
+        SsimComparator::new(0.98)
+    }
+}
+
+impl ImageComparator for SsimComparator {
+    fn compare(&self, actual: &DynamicImage, expected: &DynamicImage) -> Verdict {
+        if actual.dimensions() != expected.dimensions() {
+            let mut report = self.new_report(compare_images(actual, expected, &Tolerance::exact()), format!(
+                "Image sizes differ: actual is {}x{}, expected is {}x{}.",
+                actual.width(), actual.height(), expected.width(), expected.height()
+            ));
+            report.score = Some(0.0);
+            return Verdict::Mismatch(report);
+        }
+
+        let actual = actual.to_rgba();
+        let expected = expected.to_rgba();
+        let actual_luma = LumaPlane::from_image(&actual);
+        let expected_luma = LumaPlane::from_image(&expected);
+        let maps = ssim_maps(&actual_luma, &expected_luma, self.window_size);
+        let ssim = maps.ssim();
+        let score = if self.multi_scale {
+            ms_ssim_score(&actual_luma, &expected_luma, self.window_size, &maps)
+        } else {
+            mean(&ssim)
+        };
+
+        let total_pixels = ssim.len() as u64;
+        let stats = DiffStats {
+            total_pixels,
+            differing_pixels: ssim.iter().filter(|similarity| **similarity < self.threshold).count() as u64,
+            allowed_differing_pixels: total_pixels,
+            max_channel_delta: actual.pixels().zip(expected.pixels())
+                .map(|(a, e)| channel_delta(*a, *e))
+                .max()
+                .unwrap_or(0),
+            ignored_pixels: 0
+        };
+        let passed = score >= self.threshold;
+        let summary = format!("{} score {:.4} is {} the threshold of {}. {} of {} pixels have a local similarity below the threshold.",
+            self.name().to_uppercase(), score, if passed { "within" } else { "below" }, self.threshold,
+            stats.differing_pixels, stats.total_pixels);
+
+        let mut report = self.new_report(stats, summary);
+        report.score = Some(score);
+        if passed {
+            Verdict::Match(report)
+        } else {
+            report.debug_images.push(("similarity".to_string(), similarity_map_image(actual.width(), actual.height(), &ssim)));
+            Verdict::Mismatch(report)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use comparator::ExactComparator;
+    use tests::{rgbw, rbgw};
+
+    fn noisy_gradient(noise: u8) -> DynamicImage {
+        DynamicImage::ImageRgba8(ImageBuffer::from_fn(32, 32, |x, y| {
+            let level = (x * 8) as u8;
+            let jitter = if (x + y) % 2 == 0 { noise } else { 0 };
+            image::Rgba { data: [level.saturating_add(jitter), level.saturating_add(jitter), level.saturating_add(jitter), 255] }
+        }))
+    }
+
+    #[test]
+    fn test_identical_images_score_one() {
+        let verdict = SsimComparator::default().compare(&noisy_gradient(0), &noisy_gradient(0));
+        assert!(verdict.is_match());
+        assert!((verdict.report().score.unwrap() - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_tolerates_fine_noise() {
+        let verdict = SsimComparator::new(0.95).compare(&noisy_gradient(2), &noisy_gradient(0));
+        assert!(verdict.is_match(), "{}", verdict.report().summary);
+        assert!(!ExactComparator.compare(&noisy_gradient(2), &noisy_gradient(0)).is_match());
+    }
+
+    #[test]
+    fn test_structural_change_fails_with_similarity_map() {
+        let verdict = SsimComparator::default().compare(&rbgw(), &rgbw());
+        assert!(!verdict.is_match());
+        let report = verdict.report();
+        assert!(report.score.unwrap() < 0.98);
+        assert_eq!(report.debug_images.len(), 1);
+        assert_eq!(report.debug_images[0].0, "similarity");
+        assert_eq!(report.debug_images[0].1.dimensions(), (2, 2));
+    }
+
+    #[test]
+    fn test_multi_scale() {
+        let comparator = SsimComparator::new(0.95).with_multi_scale(true);
+        let verdict = comparator.compare(&noisy_gradient(2), &noisy_gradient(0));
+        assert_eq!(verdict.report().comparator, "ms-ssim");
+        assert!(verdict.is_match(), "{}", verdict.report().summary);
+        let inverted = noisy_gradient(0).to_rgba();
+        let mut inverted = DynamicImage::ImageRgba8(inverted);
+        inverted.invert();
+        assert!(!comparator.compare(&inverted, &noisy_gradient(0)).is_match());
+    }
+
+    #[test]
+    fn test_size_mismatch() {
+        let verdict = SsimComparator::default().compare(&DynamicImage::new_rgba8(2, 2), &DynamicImage::new_rgba8(3, 2));
+        assert!(!verdict.is_match());
+        assert_eq!(verdict.report().score, Some(0.0));
+    }
+}
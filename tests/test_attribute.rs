@@ -0,0 +1,35 @@
+#![cfg(feature = "macros")]
+
+extern crate xray;
+
+use xray::FromTestName;
+
+struct RecordedName(String);
+
+impl FromTestName for RecordedName {
+    fn from_test_name(test_name: &str) -> RecordedName {
+        RecordedName(test_name.to_string())
+    }
+}
+
+#[xray::test]
+fn derived_name(name: RecordedName, screenshot_io: xray::FsScreenshotIo) {
+    assert_eq!(name.0, "test_attribute/derived_name");
+    assert!(!screenshot_io.has_pending_reference());
+}
+
+mod menus {
+    use super::RecordedName;
+
+    #[xray::test]
+    fn main_menu(RecordedName(name): RecordedName) -> Result<(), String> {
+        assert_eq!(name, "test_attribute/menus/main_menu");
+        Ok(())
+    }
+
+    #[xray::test]
+    #[should_panic]
+    fn reports_failures(RecordedName(name): RecordedName) {
+        assert_eq!(name, "a different name");
+    }
+}
@@ -0,0 +1,37 @@
+extern crate image;
+extern crate xray;
+
+use std::env;
+use std::fs;
+use std::process;
+
+use image::DynamicImage;
+use xray::{Config, FsScreenshotIo, ScreenshotCaptor, ScreenshotIo, ScreenshotTest, XrayError, CONFIG_ENV_VAR};
+
+struct BlankScreenshotCaptor;
+
+impl ScreenshotCaptor for BlankScreenshotCaptor {
+    fn capture_image(&self, _x: i32, _y: i32, width: u32, height: u32) -> Result<DynamicImage, XrayError> {
+        Ok(DynamicImage::new_rgba8(width, height))
+    }
+}
+
+// Each integration test file runs in its own process, so this is the first use of the global configuration.
+#[test]
+fn malformed_config_is_returned_as_an_error() {
+    let dir = env::temp_dir().join(format!("xray-config-error-test-{}", process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("xray.toml"), "references = [").unwrap();
+    env::set_var(CONFIG_ENV_VAR, dir.join("xray.toml"));
+
+    match Config::global() {
+        Err(XrayError::Config(err)) => assert_eq!(err.path, dir.join("xray.toml")),
+        _ => panic!("Expected a configuration error")
+    }
+    let screenshot_io = FsScreenshotIo::default("malformed_config");
+    match ScreenshotTest::new(screenshot_io, BlankScreenshotCaptor).with_region(0, 0, 2, 2).run() {
+        Err(XrayError::Config(_)) => {},
+        _ => panic!("Expected a configuration error")
+    }
+    fs::remove_dir_all(&dir).unwrap();
+}
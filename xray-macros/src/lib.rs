@@ -0,0 +1,63 @@
+//! The `#[xray::test]` attribute, re-exported by the xray crate. See `xray::test` for usage.
+
+extern crate proc_macro;
+extern crate proc_macro2;
+#[macro_use]
+extern crate quote;
+extern crate syn;
+
+use proc_macro::TokenStream;
+use proc_macro2::Span;
+use syn::spanned::Spanned;
+use syn::{FnArg, ItemFn, ReturnType};
+
+/// Turns a function into a screenshot test named after its module path and function name.
+///
+/// Each argument of the function is created with `xray::FromTestName::from_test_name`, given the
+/// test name, e.g. an `FsScreenshotIo` reading `references/<test name>.png` and an `OpenGlScreenshotCaptor`.
+#[proc_macro_attribute]
+pub fn test(args: TokenStream, input: TokenStream) -> TokenStream {
+    if !args.is_empty() {
+        return compile_error(Span::call_site(), "#[xray::test] does not take any arguments");
+    }
+    let function: ItemFn = match syn::parse(input) {
+        Ok(function) => function,
+        Err(_) => return compile_error(Span::call_site(), "#[xray::test] can only be used on functions")
+    };
+    if !function.sig.generics.params.is_empty() {
+        return compile_error(function.sig.generics.span(), "#[xray::test] functions can not be generic");
+    }
+
+    let mut argument_types = Vec::new();
+    for input in &function.sig.inputs {
+        match input {
+            FnArg::Typed(argument) => argument_types.push(argument.ty.clone()),
+            _ => return compile_error(input.span(), "#[xray::test] arguments must have a pattern and a type, e.g. `screenshot_io: FsScreenshotIo`")
+        }
+    }
+
+    let attrs = &function.attrs;
+    let vis = &function.vis;
+    let ident = &function.sig.ident;
+    let inputs = &function.sig.inputs;
+    let block = &function.block;
+    let output = match function.sig.output {
+        ReturnType::Default => quote!(),
+        ReturnType::Type(ref arrow, ref ty) => quote!(#arrow #ty)
+    };
+    let expanded = quote! {
+        #[test]
+        #(#attrs)*
+        #vis fn #ident() #output {
+            fn #ident(#inputs) #output #block
+            let test_name = ::xray::test_name(module_path!(), stringify!(#ident));
+            #ident(#(<#argument_types as ::xray::FromTestName>::from_test_name(&test_name)),*)
+        }
+    };
+    expanded.into()
+}
+
+fn compile_error(span: Span, message: &str) -> TokenStream {
+    let error = quote_spanned!(span => compile_error!(#message););
+    error.into()
+}